@@ -0,0 +1,84 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: &str, span: Span) -> ParseError {
+        ParseError {
+            message: message.to_owned(),
+            span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrPart {
+    Lit(String),
+    Code(String, Span),
+}
+
+/* A string literal once parsed, with each interpolation as its statements: */
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Lit(String),
+    Code(Vec<Node>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub params: Vec<String>,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeKind {
+    Str(Vec<Segment>),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Nil,
+    Sym(String),
+    Regex(String),
+    Array(Vec<Node>),
+    Hash(Vec<(Node, Node)>),
+    Ident(String),
+    Const(String),
+    Assign(Box<Node>, Box<Node>),
+    Index(Box<Node>, Vec<Node>),
+    Call {
+        recv: Option<Box<Node>>,
+        name: String,
+        args: Vec<Node>,
+        block: Option<Block>,
+    },
+    If(Box<Node>, Vec<Node>, Vec<Node>),
+    While(Box<Node>, Vec<Node>),
+    Break,
+    Def(String, Vec<(String, Option<Node>)>, Vec<Node>),
+    Return(Option<Box<Node>>),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Binary(String, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(kind: NodeKind, span: Span) -> Node {
+        Node { kind, span }
+    }
+}
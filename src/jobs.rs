@@ -0,0 +1,62 @@
+use std::sync::{Condvar, Mutex};
+
+struct State {
+    workers: usize,
+    started: Vec<String>,
+    finished: Vec<String>,
+}
+
+/* Shared between the threads of a run: free worker slots and which tasks
+   have been invoked, so that each task still runs only once. */
+pub struct Jobs {
+    state: Mutex<State>,
+    cvar: Condvar,
+}
+
+impl Jobs {
+    pub fn new(workers: usize) -> Jobs {
+        Jobs {
+            state: Mutex::new(State {
+                workers,
+                started: Vec::new(),
+                finished: Vec::new(),
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /* Claims a task for the calling thread. If another thread has already
+       claimed it, waits for that thread to finish it and returns false. */
+    pub fn claim(&self, name: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.started.iter().any(|s| s == name) {
+            state.started.push(name.to_owned());
+            return true;
+        }
+        while !state.finished.iter().any(|f| f == name) {
+            state = self.cvar.wait(state).unwrap();
+        }
+        false
+    }
+
+    pub fn finish(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.finished.push(name.to_owned());
+        self.cvar.notify_all();
+    }
+
+    /* Takes a worker slot if one is free; otherwise the caller runs the task itself. */
+    pub fn take_worker(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.workers == 0 {
+            return false;
+        }
+        state.workers -= 1;
+        true
+    }
+
+    pub fn release_worker(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.workers += 1;
+    }
+}
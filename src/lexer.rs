@@ -0,0 +1,738 @@
+use ast::{ParseError, Span, StrPart};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Ident(String),
+    Const(String),
+    Label(String),
+    Sym(String),
+    Str(Vec<StrPart>),
+    Int(i64),
+    Float(f64),
+    Regex(String),
+    Words(Vec<String>),
+    Op(String),
+    Do,
+    End,
+    True,
+    False,
+    Nil,
+    If,
+    Unless,
+    While,
+    Until,
+    Break,
+    Def,
+    Return,
+    Then,
+    Else,
+    Elsif,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Dot,
+    ColonColon,
+    Arrow,
+    Assign,
+    Pipe,
+    Semi,
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub tok: Tok,
+    pub span: Span,
+    pub spaced: bool,
+}
+
+/* Operators, longest first so that e.g. "<=>" wins over "<=": */
+const OPERATORS: [&str; 30] = [
+    "**=", "<=>", "===", "...", "||=", "&&=",
+    "**", "==", "!=", "=~", "!~", "<=", ">=", "<<", ">>", "&&", "||",
+    "+=", "-=", "*=", "/=", "..", "+", "-", "*", "/", "%", "<", ">", "!",
+];
+
+fn keyword(word: &str) -> Option<Tok> {
+    match word {
+        "do" => Some(Tok::Do),
+        "end" => Some(Tok::End),
+        "true" => Some(Tok::True),
+        "false" => Some(Tok::False),
+        "nil" => Some(Tok::Nil),
+        "if" => Some(Tok::If),
+        "unless" => Some(Tok::Unless),
+        "while" => Some(Tok::While),
+        "until" => Some(Tok::Until),
+        "break" => Some(Tok::Break),
+        "def" => Some(Tok::Def),
+        "return" => Some(Tok::Return),
+        "then" => Some(Tok::Then),
+        "else" => Some(Tok::Else),
+        "elsif" => Some(Tok::Elsif),
+        _ => None,
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    tokens: Vec<Token>,
+    // Where lexing resumes, and on which line, after the heredocs begun on this line.
+    heredoc_end: Option<(usize, usize)>,
+    // Columns stripped from the start of each line, as by a `<<~` heredoc.
+    margin: usize,
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> char {
+        *self.chars.get(self.pos + offset).unwrap_or(&'\0')
+    }
+
+    fn bump(&mut self) -> char {
+        let c = self.peek(0);
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1 + self.margin;
+        } else {
+            self.col += 1;
+        }
+        c
+    }
+
+    fn span(&self) -> Span {
+        Span { line: self.line, col: self.col }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.pos == 0 || self.chars[self.pos - 1] == '\n'
+    }
+
+    fn push(&mut self, tok: Tok, span: Span, spaced: bool) {
+        self.tokens.push(Token { tok, span, spaced });
+    }
+
+    /* A newline is insignificant when the next line continues a method chain: */
+    fn continues_chain(&self) -> bool {
+        let mut i = self.pos;
+        while i < self.chars.len() && self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        i + 1 < self.chars.len() && self.chars[i] == '.' && self.chars[i + 1] != '.'
+    }
+
+    fn last_is_newline(&self) -> bool {
+        match self.tokens.last() {
+            Some(t) => t.tok == Tok::Newline,
+            None => true,
+        }
+    }
+
+    fn lex(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut spaced = true;
+        loop {
+            let c = self.peek(0);
+            let span = self.span();
+            if c == '\0' {
+                break;
+            }
+            if c == '=' && self.at_line_start() && self.starts_with("=begin") {
+                self.skip_block_comment(span)?;
+                continue;
+            }
+            if c == ' ' || c == '\t' || c == '\r' {
+                self.bump();
+                spaced = true;
+                continue;
+            }
+            if c == '\\' && self.peek(1) == '\n' {
+                self.bump();
+                self.bump();
+                spaced = true;
+                continue;
+            }
+            if c == '#' {
+                while self.peek(0) != '\n' && self.peek(0) != '\0' {
+                    self.bump();
+                }
+                continue;
+            }
+            if c == '\n' {
+                self.bump();
+                if let Some((end, line)) = self.heredoc_end.take() {
+                    self.pos = end;
+                    self.line = line;
+                }
+                if !self.last_is_newline() && !self.continues_chain() {
+                    self.push(Tok::Newline, span, spaced);
+                }
+                spaced = true;
+                continue;
+            }
+            let tok = if c.is_ascii_digit() {
+                self.number(span)?
+            } else if is_ident_start(c) {
+                self.word()
+            } else if c == '"' {
+                self.bump();
+                Tok::Str(self.double_quoted('"', span)?)
+            } else if c == '\'' {
+                self.bump();
+                Tok::Str(vec![StrPart::Lit(self.single_quoted(span)?)])
+            } else if c == '/' && self.regex_allowed(spaced) {
+                self.bump();
+                Tok::Regex(self.regex(span)?)
+            } else if c == '%' && self.peek(1) == 'w' && "[({<".contains(self.peek(2))
+            && self.regex_allowed(spaced) {
+                self.bump();
+                self.bump();
+                Tok::Words(self.words(span)?)
+            } else if c == '<' && self.peek(1) == '<' && self.starts_heredoc()
+            && self.regex_allowed(spaced) {
+                self.heredoc(span)?
+            } else if c == ':' && self.peek(1) == ':' {
+                self.bump();
+                self.bump();
+                Tok::ColonColon
+            } else if c == ':' && is_ident_start(self.peek(1)) {
+                self.bump();
+                match self.word() {
+                    Tok::Ident(s) | Tok::Const(s) => Tok::Sym(s),
+                    _ => unreachable!(),
+                }
+            } else if c == ':' && self.peek(1) == '"' {
+                self.bump();
+                self.bump();
+                let parts = self.double_quoted('"', span)?;
+                Tok::Sym(parts.iter().map(|p| match *p {
+                    StrPart::Lit(ref s) | StrPart::Code(ref s, _) => s.clone(),
+                }).collect())
+            } else {
+                self.punct(span)?
+            };
+            self.push(tok, span, spaced);
+            spaced = false;
+        }
+        let span = self.span();
+        if !self.last_is_newline() {
+            self.push(Tok::Newline, span, spaced);
+        }
+        self.push(Tok::Eof, span, true);
+        Ok(self.tokens)
+    }
+
+    /* A slash starts a regex, rather than dividing, where no operand precedes it. */
+    fn regex_allowed(&self, spaced: bool) -> bool {
+        match self.tokens.last().map(|t| &t.tok) {
+            None => true,
+            Some(&Tok::Ident(_)) => spaced && !self.peek(1).is_whitespace(),
+            Some(&Tok::Const(_)) | Some(&Tok::Sym(_)) | Some(&Tok::Str(_))
+            | Some(&Tok::Int(_)) | Some(&Tok::Float(_)) | Some(&Tok::Regex(_))
+            | Some(&Tok::RParen) | Some(&Tok::RBracket) | Some(&Tok::RBrace)
+            | Some(&Tok::End) => false,
+            Some(_) => true,
+        }
+    }
+
+    fn regex(&mut self, span: Span) -> Result<String, ParseError> {
+        let mut pattern = String::new();
+        loop {
+            let c = self.bump();
+            match c {
+                '\0' | '\n' => return Err(ParseError::new("unterminated regexp", span)),
+                '/' => break,
+                '\\' => {
+                    pattern.push(c);
+                    pattern.push(self.bump());
+                },
+                _ => pattern.push(c),
+            }
+        }
+        let mut flags = String::new();
+        while self.peek(0) == 'i' || self.peek(0) == 'm' || self.peek(0) == 'x' {
+            match self.bump() {
+                'm' => flags.push('s'),
+                f => flags.push(f),
+            }
+        }
+        if !flags.is_empty() {
+            pattern = format!("(?{}){}", flags, pattern);
+        }
+        Ok(pattern)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek(i) == c)
+    }
+
+    fn skip_block_comment(&mut self, span: Span) -> Result<(), ParseError> {
+        loop {
+            while self.peek(0) != '\n' {
+                if self.peek(0) == '\0' {
+                    return Err(ParseError::new("embedded document meets end of file", span));
+                }
+                self.bump();
+            }
+            self.bump();
+            if self.starts_with("=end") {
+                while self.peek(0) != '\n' && self.peek(0) != '\0' {
+                    self.bump();
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    fn number(&mut self, span: Span) -> Result<Tok, ParseError> {
+        let mut text = String::new();
+        while self.peek(0).is_ascii_digit() || self.peek(0) == '_' {
+            let c = self.bump();
+            if c != '_' {
+                text.push(c);
+            }
+        }
+        let mut float = false;
+        if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
+            float = true;
+            text.push(self.bump());
+            while self.peek(0).is_ascii_digit() || self.peek(0) == '_' {
+                let c = self.bump();
+                if c != '_' {
+                    text.push(c);
+                }
+            }
+        }
+        if (self.peek(0) == 'e' || self.peek(0) == 'E')
+        && (self.peek(1).is_ascii_digit()
+        || ((self.peek(1) == '-' || self.peek(1) == '+') && self.peek(2).is_ascii_digit())) {
+            float = true;
+            text.push(self.bump());
+            text.push(self.bump());
+            while self.peek(0).is_ascii_digit() {
+                text.push(self.bump());
+            }
+        }
+        if float {
+            match text.parse::<f64>() {
+                Ok(f) => Ok(Tok::Float(f)),
+                Err(_) => Err(ParseError::new(&format!("invalid number '{}'", text), span)),
+            }
+        } else {
+            match text.parse::<i64>() {
+                Ok(n) => Ok(Tok::Int(n)),
+                Err(_) => Err(ParseError::new(&format!("integer '{}' too big", text), span)),
+            }
+        }
+    }
+
+    fn word(&mut self) -> Tok {
+        let mut word = String::new();
+        while is_ident_char(self.peek(0)) {
+            word.push(self.bump());
+        }
+        if (self.peek(0) == '?' || self.peek(0) == '!')
+        && self.peek(1) != '=' && self.peek(1) != ':' {
+            word.push(self.bump());
+        }
+        if self.peek(0) == ':' && self.peek(1) != ':' && !word.ends_with('?') {
+            self.bump();
+            return Tok::Label(word);
+        }
+        if let Some(kw) = keyword(&word) {
+            return kw;
+        }
+        if word.chars().next().unwrap().is_uppercase() {
+            Tok::Const(word)
+        } else {
+            Tok::Ident(word)
+        }
+    }
+
+    fn escape(&mut self, span: Span) -> Result<char, ParseError> {
+        match self.bump() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            'e' => Ok('\x1b'),
+            's' => Ok(' '),
+            '0' => Ok('\0'),
+            '\0' => Err(ParseError::new("unterminated string meets end of file", span)),
+            c => Ok(c),
+        }
+    }
+
+    fn double_quoted(&mut self, close: char, span: Span) -> Result<Vec<StrPart>, ParseError> {
+        let mut parts: Vec<StrPart> = Vec::new();
+        let mut lit = String::new();
+        loop {
+            let c = self.bump();
+            if c == close {
+                break;
+            } else if c == '\0' {
+                return Err(ParseError::new("unterminated string meets end of file", span));
+            } else if c == '\\' {
+                let e = self.escape(span)?;
+                lit.push(e);
+            } else if c == '#' && self.peek(0) == '{' {
+                self.bump();
+                if !lit.is_empty() {
+                    parts.push(StrPart::Lit(lit.clone()));
+                    lit.clear();
+                }
+                let at = self.span();
+                parts.push(StrPart::Code(self.interpolation(span)?, at));
+            } else {
+                lit.push(c);
+            }
+        }
+        if !lit.is_empty() || parts.is_empty() {
+            parts.push(StrPart::Lit(lit));
+        }
+        Ok(parts)
+    }
+
+    /* Raw source of a #{...} interpolation, up to its matching brace: */
+    fn interpolation(&mut self, span: Span) -> Result<String, ParseError> {
+        let mut code = String::new();
+        let mut depth = 0;
+        let mut quote: Option<char> = None;
+        loop {
+            let c = self.bump();
+            if c == '\0' {
+                return Err(ParseError::new("unterminated interpolation meets end of file", span));
+            }
+            match quote {
+                Some(q) => {
+                    if c == '\\' {
+                        code.push(c);
+                        code.push(self.bump());
+                        continue;
+                    }
+                    if c == q {
+                        quote = None;
+                    }
+                },
+                None => {
+                    if c == '"' || c == '\'' {
+                        quote = Some(c);
+                    } else if c == '{' {
+                        depth += 1;
+                    } else if c == '}' {
+                        if depth == 0 {
+                            break;
+                        }
+                        depth -= 1;
+                    }
+                },
+            }
+            code.push(c);
+        }
+        Ok(code)
+    }
+
+    fn starts_heredoc(&self) -> bool {
+        let c = match self.peek(2) {
+            '~' | '-' => self.peek(3),
+            c => c,
+        };
+        c == '"' || c == '\'' || is_ident_start(c)
+    }
+
+    /* A `<<~ID`, `<<-ID` or `<<ID` heredoc. The body runs from the next line up to
+       the ID on a line of its own, indented unless plain `<<`. `<<~` also strips the
+       body's common indentation, and quoting the ID as 'ID' turns off interpolation. */
+    fn heredoc(&mut self, span: Span) -> Result<Tok, ParseError> {
+        self.bump();
+        self.bump();
+        let squiggly = self.peek(0) == '~';
+        let indented = squiggly || self.peek(0) == '-';
+        if indented {
+            self.bump();
+        }
+        let quote = match self.peek(0) {
+            '"' | '\'' => Some(self.bump()),
+            _ => None,
+        };
+        let mut id = String::new();
+        while is_ident_char(self.peek(0)) {
+            id.push(self.bump());
+        }
+        if let Some(q) = quote {
+            if self.bump() != q {
+                return Err(ParseError::new("unterminated heredoc identifier", span));
+            }
+        }
+        let (mut i, mut line) = match self.heredoc_end {
+            Some(end) => end,
+            None => {
+                let mut i = self.pos;
+                while i < self.chars.len() && self.chars[i] != '\n' {
+                    i += 1;
+                }
+                (i + 1, self.line + 1)
+            },
+        };
+        let start_line = line;
+        let mut lines: Vec<String> = Vec::new();
+        loop {
+            if i >= self.chars.len() {
+                return Err(ParseError::new(
+                    &format!("can't find string \"{}\" anywhere before EOF", id), span));
+            }
+            let mut text = String::new();
+            while i < self.chars.len() && self.chars[i] != '\n' {
+                text.push(self.chars[i]);
+                i += 1;
+            }
+            i += 1;
+            line += 1;
+            let text = text.trim_end_matches('\r').to_owned();
+            if text == id || (indented && text.trim_start() == id) {
+                break;
+            }
+            lines.push(text);
+        }
+        self.heredoc_end = Some((i, line));
+        let mut margin = 0;
+        if squiggly {
+            // Indentation is spaces and tabs only, so it is as many bytes as chars.
+            let indentation = |l: &str| l.len() - l.trim_start_matches([' ', '\t']).len();
+            let indent = lines.iter().filter(|l| !l.trim().is_empty())
+            .map(|l| indentation(l)).min().unwrap_or(0);
+            for l in &mut lines {
+                let strip = indent.min(indentation(l));
+                l.drain(..strip);
+            }
+            margin = indent;
+        }
+        let body: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+        if quote == Some('\'') {
+            return Ok(Tok::Str(vec![StrPart::Lit(body)]));
+        }
+        // The body reads as a double-quoted string running to its end.
+        let mut lexer = Lexer {
+            chars: body.chars().collect(),
+            pos: 0,
+            line: start_line,
+            col: 1 + margin,
+            tokens: Vec::new(),
+            heredoc_end: None,
+            margin,
+        };
+        let at = lexer.span();
+        Ok(Tok::Str(lexer.double_quoted('\0', at)?))
+    }
+
+    /* A `%w[a b c]` list, with any of the bracket pairs as delimiters. */
+    fn words(&mut self, span: Span) -> Result<Vec<String>, ParseError> {
+        let close = match self.bump() {
+            '(' => ')',
+            '{' => '}',
+            '<' => '>',
+            _ => ']',
+        };
+        let mut words: Vec<String> = Vec::new();
+        let mut word = String::new();
+        loop {
+            match self.bump() {
+                '\0' => return Err(ParseError::new("unterminated list meets end of file", span)),
+                c if c == close || c.is_whitespace() => {
+                    if !word.is_empty() {
+                        words.push(word.clone());
+                        word.clear();
+                    }
+                    if c == close {
+                        break;
+                    }
+                },
+                '\\' if self.peek(0).is_whitespace() || self.peek(0) == close => {
+                    word.push(self.bump());
+                },
+                c => word.push(c),
+            }
+        }
+        Ok(words)
+    }
+
+    fn single_quoted(&mut self, span: Span) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            let c = self.bump();
+            match c {
+                '\0' => return Err(ParseError::new("unterminated string meets end of file", span)),
+                '\'' => break,
+                '\\' if self.peek(0) == '\'' || self.peek(0) == '\\' => s.push(self.bump()),
+                _ => s.push(c),
+            }
+        }
+        Ok(s)
+    }
+
+    fn punct(&mut self, span: Span) -> Result<Tok, ParseError> {
+        let c = self.peek(0);
+        let single = match c {
+            '(' => Some(Tok::LParen),
+            ')' => Some(Tok::RParen),
+            '[' => Some(Tok::LBracket),
+            ']' => Some(Tok::RBracket),
+            '{' => Some(Tok::LBrace),
+            '}' => Some(Tok::RBrace),
+            ',' => Some(Tok::Comma),
+            ';' => Some(Tok::Semi),
+            _ => None,
+        };
+        if let Some(tok) = single {
+            self.bump();
+            return Ok(tok);
+        }
+        if self.starts_with("=>") {
+            self.bump();
+            self.bump();
+            return Ok(Tok::Arrow);
+        }
+        for op in OPERATORS.iter() {
+            if self.starts_with(op) {
+                for _ in 0..op.len() {
+                    self.bump();
+                }
+                return Ok(Tok::Op(op.to_string()));
+            }
+        }
+        match c {
+            '=' => {
+                self.bump();
+                Ok(Tok::Assign)
+            },
+            '|' => {
+                self.bump();
+                Ok(Tok::Pipe)
+            },
+            '.' => {
+                self.bump();
+                Ok(Tok::Dot)
+            },
+            '&' | '?' | ':' | '^' | '~' => {
+                self.bump();
+                Ok(Tok::Op(c.to_string()))
+            },
+            _ => Err(ParseError::new(&format!("unexpected character '{}'", c), span)),
+        }
+    }
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let lexer = Lexer {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+        tokens: Vec::new(),
+        heredoc_end: None,
+        margin: 0,
+    };
+    lexer.lex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Tok};
+    use ast::{Span, StrPart};
+
+    fn toks(source: &str) -> Vec<Tok> {
+        tokenize(source).unwrap().into_iter().map(|t| t.tok).collect()
+    }
+
+    fn lit(s: &str) -> Tok {
+        Tok::Str(vec![StrPart::Lit(s.to_owned())])
+    }
+
+    fn ident(s: &str) -> Tok {
+        Tok::Ident(s.to_owned())
+    }
+
+    #[test]
+    fn squiggly_heredoc_dedents_and_lexing_resumes_after_it() {
+        let tokens = tokenize("x = <<~EOS + \"!\"\n    a\n      b\n  EOS\ny\n").unwrap();
+        let kinds: Vec<Tok> = tokens.iter().map(|t| t.tok.clone()).collect();
+        assert_eq!(kinds, vec![ident("x"), Tok::Assign, lit("a\n  b\n"), Tok::Op("+".to_owned()),
+        lit("!"), Tok::Newline, ident("y"), Tok::Newline, Tok::Eof]);
+        assert_eq!(tokens[6].span, Span { line: 5, col: 1 });
+    }
+
+    #[test]
+    fn heredocs_on_one_line_read_their_bodies_in_order() {
+        assert_eq!(toks("f <<-A, <<'B'\n  a #{x}\n  A\nb #{x}\nB\n"), vec![ident("f"),
+        Tok::Str(vec![StrPart::Lit("  a ".to_owned()), StrPart::Code("x".to_owned(), Span { line: 2, col: 7 }),
+        StrPart::Lit("\n".to_owned())]), Tok::Comma, lit("b #{x}\n"), Tok::Newline, Tok::Eof]);
+        assert!(tokenize("puts <<~EOS\n  a\n").is_err());
+    }
+
+    #[test]
+    fn heredoc_dedent_counts_only_spaces_and_tabs() {
+        assert_eq!(toks("<<~E\n  a\n\u{3000}b\nE\n")[0], lit("  a\n\u{3000}b\n"));
+    }
+
+    #[test]
+    fn interpolation_nests_and_skips_braces_in_quotes() {
+        assert_eq!(toks("\"a#{\"}\" + \"b#{1}\"}c\"")[0], Tok::Str(vec![
+            StrPart::Lit("a".to_owned()),
+            StrPart::Code("\"}\" + \"b#{1}\"".to_owned(), Span { line: 1, col: 5 }),
+            StrPart::Lit("c".to_owned()),
+        ]));
+        assert_eq!(toks("\"#{ {a: 1}[:a] }\"")[0], Tok::Str(vec![
+            StrPart::Code(" {a: 1}[:a] ".to_owned(), Span { line: 1, col: 4 }),
+        ]));
+    }
+
+    #[test]
+    fn slash_divides_after_an_operand_and_starts_a_regex_otherwise() {
+        let slash = Tok::Op("/".to_owned());
+        assert_eq!(toks("a / b")[1], slash);
+        assert_eq!(toks("6/2")[1], slash);
+        assert_eq!(toks("(a) / 2")[3], slash);
+        assert_eq!(toks("puts /a b/")[1], Tok::Regex("a b".to_owned()));
+        assert_eq!(toks("x =~ /\\d+/")[2], Tok::Regex("\\d+".to_owned()));
+    }
+
+    #[test]
+    fn word_lists() {
+        let words = |w: &[&str]| Tok::Words(w.iter().map(|s| s.to_string()).collect());
+        assert_eq!(toks("%w[a  b\\ c]")[0], words(&["a", "b c"]));
+        assert_eq!(toks("%w(x\ny)")[0], words(&["x", "y"]));
+        assert_eq!(toks("%w<>")[0], words(&[]));
+        assert_eq!(toks("a %w")[1], Tok::Op("%".to_owned()));
+    }
+
+    #[test]
+    fn labels_symbols_and_scopes() {
+        assert_eq!(toks("f a: :b, c: 1"), vec![ident("f"), Tok::Label("a".to_owned()),
+        Tok::Sym("b".to_owned()), Tok::Comma, Tok::Label("c".to_owned()), Tok::Int(1),
+        Tok::Newline, Tok::Eof]);
+        assert_eq!(toks("File::exist?")[1..3], [Tok::ColonColon, ident("exist?")]);
+        assert_eq!(toks(":\"a b\"")[0], Tok::Sym("a b".to_owned()));
+    }
+
+    #[test]
+    fn error_spans() {
+        let e = tokenize("x = 1\n  y = \"abc").unwrap_err();
+        assert_eq!((e.message.as_str(), e.span), ("unterminated string meets end of file", Span { line: 2, col: 7 }));
+        let e = tokenize("a = 1 $ 2").unwrap_err();
+        assert_eq!(e.span, Span { line: 1, col: 7 });
+    }
+}
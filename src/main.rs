@@ -6,542 +6,1681 @@
     Released under the MIT License.
 */
 
+mod ast;
+mod lexer;
+mod parser;
 mod variable;
 mod task;
 mod rstruct;
+mod rule;
+mod jobs;
+mod method;
+mod value;
 extern crate clioptions;
 extern crate regex;
-extern crate os_type;
+use ast::{Block, Node, NodeKind, ParseError, Segment};
 use variable::Variable;
-use task::Task;
+use task::{Kind, Task};
 use rstruct::Struct;
+use rule::Rule;
+use jobs::Jobs;
+use method::Method;
+use value::Value;
 use clioptions::CliOptions;
 use regex::Regex;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::fs::{self, File};
-use std::path::Path;
-use std::{thread, time, env};
-use std::process::{Command, Stdio, exit};
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::time::SystemTime;
+use std::{thread, time, env, iter};
+use std::process::{Command, ExitStatus, Stdio, exit};
+
+#[derive(PartialEq)]
+enum Display {
+    Run,
+    Tasks,
+    Describe,
+    Prereqs,
+    Where,
+    Dot,
+    Json,
+}
+
+/* How control leaves the statement being run: */
+#[derive(Clone, PartialEq)]
+enum Flow {
+    Normal,
+    Break,
+    Return(Value),
+}
 
 struct Options {
     verbose: bool,
     exit_codes: bool,
     ignore: bool,
+    jobs: usize,
+    display: Display,
+    pattern: String,
+    show_all: bool,
+    dry_run: bool,
+    trace: bool,
+    rakelib: String,
 }
 
-fn get_struct_fields(cfields: &str) -> Vec<String> {
-    let split = cfields.split(",");
-    let fields: Vec<&str> = split.collect();
-    let mut ffields: Vec<String> = Vec::new();
-    for (i, f) in fields.iter().enumerate() {
-        let ff: String;
-        if i == 0 {
-            ff = format!("{}", &f[1..].trim());
-        } else if i == fields.len() - 1 {
-            ff = format!("{}", &f[0..f.len() - 1].trim());
-        } else {
-            ff = format!("{}", f.trim());
-        }
-        if !ff.is_empty() {
-            ffields.push(ff.to_owned());
-        }
-    }
-    ffields
+#[derive(Clone)]
+struct Context<'a> {
+    program: &'a str,
+    rakefile: String,
+    imports: Vec<(String, usize)>,
+    opts: &'a Options,
+    vars: Vec<Variable>,
+    structs: Vec<Struct>,
+    methods: Vec<Method>,
+    tasks: Vec<Task>,
+    rules: Vec<Rule>,
+    wkdir: PathBuf,
+    task: String,
+    desc: String,
+    scope: Vec<String>,
+    task_param: String,
+    args_param: String,
+    args: Vec<(String, String)>,
+    status_param: String,
+    status: Option<ExitStatus>,
+    flow: Flow,
+    parallel: bool,
 }
 
-fn get_struct_values(cvalues: &str) -> Vec<String> {
-    let split = cvalues.split(",");
-    let values: Vec<&str> = split.collect();
-    let mut fvalues: Vec<String> = Vec::new();
-    for (i, v) in values.iter().enumerate() {
-        let vv: String;
-        if i == 0 {
-            vv = format!("{}", &v[1..].trim());
-        } else if i == values.len() - 1 {
-            vv = format!("{}", &v[0..v.len() - 1].trim());
-        } else {
-            vv = format!("{}", v.trim());
-        }
-        if !vv.is_empty() {
-            fvalues.push(vv.to_owned());
+struct Resolution {
+    chain: Vec<String>,
+    invoked: Vec<String>,
+    tasks: Vec<Task>,
+}
+
+impl<'a> Context<'a> {
+    fn get_var(&self, key: &str) -> Option<Value> {
+        self.vars.iter().find(|v| v.get_key() == key).map(|v| v.get_value())
+    }
+    fn set_var(&mut self, key: &str, value: Value) {
+        if let Some(v) = self.vars.iter_mut().find(|v| v.get_key() == key) {
+            v.set_value(value);
+            return;
         }
+        self.vars.push(Variable::new(key, value));
     }
-    fvalues
 }
 
-fn validate_rakefile(rakefile: &str) -> bool {
-    let mut valid = false;
+/* Whether statements define any task or rule, or pull in files that might: */
+fn defines_tasks(stmts: &[Node]) -> bool {
+    stmts.iter().any(|stmt| match stmt.kind {
+        NodeKind::Call { recv: None, ref name, ref block, .. } => {
+            ["task", "multitask", "file", "directory", "rule", "import", "load"].contains(&name.as_str())
+            || block.as_ref().map(|b| defines_tasks(&b.body)).unwrap_or(false)
+        },
+        NodeKind::If(_, ref body, ref otherwise) => defines_tasks(body) || defines_tasks(otherwise),
+        NodeKind::While(_, ref body) => defines_tasks(body),
+        _ => false,
+    })
+}
+
+/* A Rakefile parses and defines something, in any of the forms the parser takes. */
+fn validate_rakefile(program: &str, rakefile: &str) -> bool {
     let mut rf = String::new();
-    let mut file = File::open(&rakefile).unwrap();
+    let mut file = File::open(rakefile).unwrap();
     let _ = file.read_to_string(&mut rf);
-    let p = Regex::new("task .* do").unwrap();
-    if p.is_match(&rf) {
-        valid = true;
+    match parser::parse(&rf) {
+        Ok(program_ast) => defines_tasks(&program_ast),
+        Err(e) => throw_parse_failure(program, rakefile, &e),
     }
-    valid
 }
 
 fn validate_extension(rakefile: &str) -> bool {
-    let mut valid = false;
-    let p = Regex::new("Rakefile|rakefile|^.rb$").unwrap();
-    if p.is_match(&rakefile) {
-        valid = true;
-    }
-    valid
+    let p = Regex::new(r"Rakefile|rakefile|\.rb$|\.rake$").unwrap();
+    p.is_match(rakefile)
 }
 
 fn parse_unit(unit: &str) -> i32 {
-    let n = unit.parse::<i32>().ok();
-    let unit = match n {
-        Some(unit) => unit as i32,
-        None => 0 as i32,
+    unit.parse::<i32>().unwrap_or(0)
+}
+
+fn interpolate(segments: &[Segment], ctx: &mut Context) -> String {
+    let mut s = String::new();
+    for segment in segments {
+        match *segment {
+            Segment::Lit(ref l) => s.push_str(l),
+            Segment::Code(ref stmts) => s.push_str(&exec_stmts(stmts, ctx).to_s()),
+        }
+    }
+    s
+}
+
+fn qualified_name(recv: &Option<Box<Node>>, name: &str) -> Option<String> {
+    match *recv {
+        None => Some(name.to_owned()),
+        Some(ref r) => match r.kind {
+            NodeKind::Const(ref c) => Some(format!("{}.{}", c, name)),
+            _ => None,
+        },
+    }
+}
+
+fn name_list(node: &Node, ctx: &mut Context) -> Vec<String> {
+    match eval_node(node, ctx) {
+        Value::Array(items) => items.iter().map(|i| i.to_s()).collect(),
+        value => vec![value.to_s()],
+    }
+}
+
+/* Defines or enhances a task from any of Rake's forms: `task :name`,
+   `task :name => deps`, `task :name, [:arg]` and `task :name, [:arg] => deps`. */
+fn define_task(kind: Kind, args: &[Node], block: &Option<Block>,
+line: usize, ctx: &mut Context) -> String {
+    let (name, mut depends) = match args.first().map(|a| &a.kind) {
+        Some(NodeKind::Hash(pairs)) if !pairs.is_empty() => {
+            (eval_node(&pairs[0].0, ctx).to_s(), name_list(&pairs[0].1, ctx))
+        },
+        Some(_) => (eval_node(&args[0], ctx).to_s(), Vec::new()),
+        None => throw_runtime_failure(ctx.program, "task requires a name", line),
     };
-    unit
+    let arg_names = match args.get(1).map(|a| &a.kind) {
+        Some(NodeKind::Array(_)) => name_list(&args[1], ctx),
+        Some(NodeKind::Hash(pairs)) if !pairs.is_empty() => {
+            depends = name_list(&pairs[0].1, ctx);
+            name_list(&pairs[0].0, ctx)
+        },
+        _ => Vec::new(),
+    };
+    // Like Rake, file and directory names are paths and stay outside namespaces.
+    let name = match kind {
+        Kind::Normal | Kind::Multi if !ctx.scope.is_empty() => {
+            format!("{}:{}", ctx.scope.join(":"), name)
+        },
+        _ => name,
+    };
+    let actions: Vec<Block> = block.iter().cloned().collect();
+    if !ctx.tasks.iter().any(|t| t.get_name() == name) {
+        let mut task = Task::new(kind, &name, Vec::new(), Vec::new(), line);
+        task.set_scope(&ctx.scope);
+        task.set_file(&ctx.rakefile);
+        ctx.tasks.push(task);
+    }
+    let desc = ctx.desc.clone();
+    ctx.desc.clear();
+    let task = ctx.tasks.iter_mut().find(|t| t.get_name() == name).unwrap();
+    task.enhance(depends, actions);
+    if !desc.is_empty() {
+        task.set_desc(&desc);
+    }
+    if !arg_names.is_empty() {
+        task.set_arg_names(arg_names);
+    }
+    name
 }
 
-fn get_os() -> String {
-    let os = os_type::current_platform();
-    format!("{:?}", os.os_type)
+/* `directory "a/b"` also defines "a", so that each level depends on its parent. */
+fn define_parent_dirs(dir: &str, line: usize, ctx: &mut Context) {
+    let mut child = dir.to_owned();
+    while let Some(parent) = Path::new(&child).parent().map(|p| p.display().to_string()) {
+        if parent.is_empty() {
+            break;
+        }
+        if let Some(task) = ctx.tasks.iter_mut().find(|t| t.get_name() == child) {
+            task.enhance(vec![parent.clone()], Vec::new());
+        }
+        if !ctx.tasks.iter().any(|t| t.get_name() == parent) {
+            let mut task = Task::new(Kind::Directory, &parent, Vec::new(), Vec::new(), line);
+            task.set_scope(&ctx.scope);
+            task.set_file(&ctx.rakefile);
+            ctx.tasks.push(task);
+        }
+        child = parent;
+    }
+}
+
+fn define_rule(args: &[Node], block: &Option<Block>, line: usize, ctx: &mut Context) {
+    let (target, sources) = match args.first().map(|a| &a.kind) {
+        Some(NodeKind::Hash(pairs)) if !pairs.is_empty() => (&pairs[0].0, name_list(&pairs[0].1, ctx)),
+        Some(_) => (&args[0], Vec::new()),
+        None => throw_runtime_failure(ctx.program, "rule requires a pattern", line),
+    };
+    let pattern = match target.kind {
+        NodeKind::Regex(ref r) => r.clone(),
+        _ => format!("{}$", regex::escape(&eval_node(target, ctx).to_s())),
+    };
+    let pattern = match Regex::new(&pattern) {
+        Ok(pattern) => pattern,
+        Err(_) => throw_runtime_failure(ctx.program,
+        &format!("invalid rule pattern /{}/", pattern), line),
+    };
+    let actions: Vec<Block> = block.iter().cloned().collect();
+    ctx.rules.push(Rule::new(pattern, sources, actions, line));
+}
+
+/* A file task for `name` from the first rule whose sources can all be found or built. */
+fn synthesize_task(name: &str, tasks: &[Task], rules: &[Rule], depth: usize) -> Option<Task> {
+    if depth > 16 {
+        return None;
+    }
+    for rule in rules {
+        if !rule.matches(name) {
+            continue;
+        }
+        let sources = rule.sources_for(name);
+        if sources.iter().all(|s| tasks.iter().any(|t| t.get_name() == s)
+        || Path::new(s).exists() || synthesize_task(s, tasks, rules, depth + 1).is_some()) {
+            return Some(Task::new(Kind::File, name, sources,
+            rule.get_actions().to_vec(), rule.get_line()));
+        }
+    }
+    None
 }
 
-fn process_vars(rvars: Vec<String>, mut vars: Vec<Variable>) -> Vec<Variable> {
-    let mut prvars: Vec<String> = Vec::new();
-    let mut pvars: Vec<Variable> = Vec::new();
-    vars.reverse();
-    for (i, rvar) in rvars.iter().enumerate() {
-        if !prvars.contains(rvar) {
-            prvars.push(rvar.clone());
-            pvars.push(vars[i].clone());
+/* Rake's lookup of a prerequisite named within a namespace: the innermost enclosing
+   namespace that defines it wins, then the outer ones. Each leading `^` starts one
+   namespace further out, and `rake:` starts at the top level. */
+fn scoped_name(name: &str, scope: &[String], tasks: &[Task]) -> String {
+    let (name, scope) = match name.strip_prefix("rake:") {
+        Some(name) => (name, &scope[..0]),
+        None => {
+            let up = name.len() - name.trim_start_matches('^').len();
+            (&name[up..], &scope[..scope.len().saturating_sub(up)])
+        },
+    };
+    for n in (0..=scope.len()).rev() {
+        let mut parts = scope[..n].to_vec();
+        parts.push(name.to_owned());
+        let qualified = parts.join(":");
+        if tasks.iter().any(|t| t.get_name() == qualified) {
+            return qualified;
         }
     }
-    pvars
+    name.to_owned()
 }
 
-fn process_struct_vars(structs: &Vec<Struct>, mut pvars: Vec<Variable>) -> Vec<Variable> {
-    for v in &mut pvars {
-        for s in structs {
-            if s.get_variable() == v.get_key() {
-                v.set_value(&s.to_json());
-            }
+fn lookup_task(name: &str, ctx: &mut Context) -> Option<Task> {
+    if let Some(task) = ctx.tasks.iter().find(|t| t.get_name() == name) {
+        return Some(task.clone());
+    }
+    if let Some(task) = synthesize_task(name, &ctx.tasks, &ctx.rules, 0) {
+        ctx.tasks.push(task.clone());
+        return Some(task);
+    }
+    // An existing file with no task of its own is a prerequisite that is always met.
+    if Path::new(name).exists() {
+        return Some(Task::new(Kind::File, name, Vec::new(), Vec::new(), 0));
+    }
+    None
+}
+
+/* Depth-first: prerequisites come before the task, and each task is invoked only once.
+   The resolution chain holds the tasks currently being resolved, to catch cycles.
+   Returns the full name, which replaces the relative one among the parent's prerequisites. */
+fn resolve_task(name: &str, parent: Option<&Task>, ctx: &mut Context, res: &mut Resolution) -> String {
+    let scope = parent.map(|p| p.get_scope()).unwrap_or(&[]);
+    let name = scoped_name(name, scope, &ctx.tasks);
+    let name = name.as_str();
+    if let Some(start) = res.chain.iter().position(|c| c == name) {
+        let mut cycle: Vec<&Task> = Vec::new();
+        for c in &res.chain[start..] {
+            cycle.extend(ctx.tasks.iter().find(|t| t.get_name() == c));
         }
+        cycle.extend(ctx.tasks.iter().find(|t| t.get_name() == name));
+        throw_cycle_failure(ctx.program, &cycle);
+    }
+    if res.invoked.iter().any(|i| i == name) {
+        return name.to_owned();
     }
-    pvars
+    res.invoked.push(name.to_owned());
+    let mut task = match lookup_task(name, ctx) {
+        Some(task) => task,
+        None => match parent {
+            Some(p) => throw_no_prereq_failure(ctx.program, name, p.get_name(), p.get_line()),
+            None => throw_no_task_failure(ctx.program, name),
+        },
+    };
+    res.chain.push(name.to_owned());
+    let mut depends: Vec<String> = Vec::new();
+    for d in task.get_depends() {
+        let d = resolve_task(d, Some(&task), ctx, res);
+        if !depends.contains(&d) {
+            depends.push(d);
+        }
+    }
+    task.set_depends(depends);
+    res.chain.pop();
+    res.tasks.push(task);
+    name.to_owned()
 }
 
-fn parse_vars_in_task(task: &Task, vars: &Vec<Variable>) -> Task {
-    let split = task.get_params().split(" ");
-    let params: Vec<&str> = split.collect();
-    let mut pparams: Vec<String> = Vec::new();
-    for param in params {
-        let p = Regex::new(&format!("#{}(.*){}", 
-        regex::escape("{"), regex::escape("}"))).unwrap();
-        if p.is_match(param) {
-            for cap in p.captures_iter(param) {
-                for var in vars {
-                    if var.get_key() == cap[1].to_owned() {
-                        pparams.push(var.get_value());
-                    }
-                }
-            }
+fn timestamp(file: &str) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+/* A file task is needed when its file is missing or older than any prerequisite.
+   Directories only need creating; their timestamps never make other files out of date. */
+fn task_needed(task: &Task, tasks: &[Task]) -> bool {
+    match task.get_kind() {
+        Kind::Normal | Kind::Multi => return true,
+        Kind::Directory => return !Path::new(task.get_name()).is_dir(),
+        Kind::File => {},
+    }
+    let stamp = match timestamp(task.get_name()) {
+        Some(stamp) => stamp,
+        None => return true,
+    };
+    task.get_depends().iter().any(|d| {
+        let dtask = tasks.iter().find(|t| t.get_name() == d);
+        let kind = dtask.map(|t| t.get_kind()).unwrap_or(Kind::File);
+        kind == Kind::Normal || kind == Kind::Multi || (kind == Kind::File && match timestamp(d) {
+            Some(dstamp) => dstamp > stamp,
+            None => true,
+        })
+    })
+}
+
+/* Output from a task is prefixed with its name while tasks run in parallel. */
+fn prefixed(ctx: &Context, text: &str) -> String {
+    if !ctx.parallel {
+        return text.to_owned();
+    }
+    let lines: Vec<String> = text.split('\n').map(|l| format!("[{}] {}", ctx.task, l)).collect();
+    lines.join("\n")
+}
+
+fn make_dirs(dir: &str, line: usize, ctx: &mut Context) {
+    if ctx.opts.verbose {
+        println!("{}", prefixed(ctx, &format!("mkdir -p {}", dir)));
+    }
+    if fs::create_dir_all(ctx.wkdir.join(dir)).is_err() {
+        throw_runtime_failure(ctx.program,
+        &format!("Could not create directory - {}", dir), line);
+    }
+}
+
+/* A single command line goes through the system shell, as in Rake;
+   several arguments run the program directly, with no shell in between. */
+fn shell_command(argv: &[String]) -> Command {
+    if argv.len() > 1 {
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        return command;
+    }
+    shell_line(&argv[0])
+}
+
+/* cmd.exe parses its own command line, so it gets the line as written rather than
+   quoted the way Command::arg quotes for C programs. */
+#[cfg(windows)]
+fn shell_line(line: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    let mut command = Command::new("cmd");
+    command.arg("/C").raw_arg(line);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell_line(line: &str) -> Command {
+    let mut command = Command::new("/bin/sh");
+    command.arg("-c").arg(line);
+    command
+}
+
+/* How a multi-argument command is echoed, quoted as a shell would need it. */
+fn shell_join(argv: &[String]) -> String {
+    if argv.len() == 1 {
+        return argv[0].clone();
+    }
+    let plain = Regex::new(r"^[A-Za-z0-9_\-./=:,+@%]+$").unwrap();
+    let words: Vec<String> = argv.iter().map(|a| {
+        if plain.is_match(a) {
+            a.clone()
         } else {
-            pparams.push(param.to_owned());
+            format!("'{}'", a.replace('\'', "'\\''"))
         }
+    }).collect();
+    words.join(" ")
+}
+
+fn run_sh(argv: &[String], line: usize, ctx: &mut Context) -> ExitStatus {
+    if argv.is_empty() {
+        throw_runtime_failure(ctx.program, "wrong number of arguments for sh (expected 1+)", line);
+    }
+    if ctx.opts.verbose {
+        println!("{}", prefixed(ctx, &shell_join(argv)));
+    }
+    let mut child = shell_command(argv);
+    child.current_dir(&ctx.wkdir);
+    let status = if ctx.parallel {
+        // Buffer the output, so that concurrent commands do not interleave.
+        child.output().map(|output| {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stdout.is_empty() {
+                println!("{}", prefixed(ctx, stdout.trim_end()));
+            }
+            if !stderr.is_empty() {
+                eprintln!("{}", prefixed(ctx, stderr.trim_end()));
+            }
+            output.status
+        })
+    } else {
+        child.stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .and_then(|mut c| c.wait())
+    };
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => throw_spawn_failure(ctx.program, &argv[0], &e, line),
+    };
+    if ctx.opts.exit_codes {
+        println!("{}", prefixed(ctx, &describe_status(&status)));
     }
-    Task::new(&task.get_name(), &task.get_depends(), 
-    &task.get_command(), &pparams.join(" "), task.get_line())
+    status
 }
 
-fn invoke_rakefile(program: &str, rakefile: &str, stasks: &Vec<String>, opts: &Options) {
-    let mut rf = String::new();
-    let mut name = String::new();
-    let mut depends = String::new();
-    let mut command: String;
-    let mut params: String;
-    let mut vars: Vec<Variable> = Vec::new();
-    let mut structs: Vec<Struct> = Vec::new();
-    let mut rvars: Vec<String> = Vec::new();
-    let mut rstructs: Vec<String> = Vec::new();
-    let mut tasks: Vec<Task> = Vec::new();
-    let mut file = File::open(rakefile).unwrap();
-    let _ = file.read_to_string(&mut rf);
-    let split = rf.split("\n");
-    let lines: Vec<&str> = split.collect();
-    let mut in_block = false;
-    let mut l_puts = false;
-    for (i, l) in lines.iter().enumerate() {
-        let mut p = Regex::new("^#").unwrap();
-        if p.is_match(&l) {
-            continue;
+/* Without a block, a failed command aborts the build unless --ignore-ec is given. */
+fn check_status(status: &ExitStatus, line: usize, ctx: &Context) {
+    if status.success() || ctx.opts.ignore {
+        return;
+    }
+    match status.code() {
+        Some(ec) => throw_build_failiure(ctx.program, &ctx.task, ec, line),
+        None => throw_signal_failure(ctx.program, &ctx.task, signal_of(status).unwrap_or(0), line),
+    }
+}
+
+/* `sh "cmd" do |ok, res| ... end` hands the outcome to the block instead of aborting. */
+fn sh_block(block: &Block, status: ExitStatus, ctx: &mut Context) {
+    let params = &block.params[..block.params.len().min(2)];
+    let saved: Vec<Option<Value>> = params.iter().map(|p| ctx.get_var(p)).collect();
+    let saved_status = ctx.status;
+    let shadowed = shadow_params(params, ctx);
+    if let Some(ok) = block.params.first() {
+        ctx.set_var(ok, Value::Bool(status.success()));
+    }
+    if let Some(res) = block.params.get(1) {
+        ctx.set_var(res, Value::Str(describe_status(&status)));
+        ctx.status_param = res.clone();
+    }
+    ctx.status = Some(status);
+    exec_stmts(&block.body, ctx);
+    // Like block parameters, `ok` and `res` do not outlive the block.
+    for (param, value) in params.iter().zip(saved) {
+        match value {
+            Some(value) => ctx.set_var(param, value),
+            None => ctx.vars.retain(|v| v.get_key() != *param),
         }
-        p = Regex::new("^end").unwrap();
-        if p.is_match(&l) {
-            in_block = false;
-            continue;
+    }
+    restore_params(shadowed, ctx);
+    ctx.status = saved_status;
+}
+
+/* Methods on the process status passed to an sh block: */
+fn status_method(name: &str, line: usize, ctx: &mut Context) -> Value {
+    let status = ctx.status.unwrap();
+    match name {
+        "exitstatus" | "to_i" => status.code().map(|c| Value::Int(c as i64)).unwrap_or(Value::Nil),
+        "success?" => Value::Bool(status.success()),
+        "signaled?" => Value::Bool(signal_of(&status).is_some()),
+        "termsig" => signal_of(&status).map(|s| Value::Int(s as i64)).unwrap_or(Value::Nil),
+        "to_s" => Value::Str(describe_status(&status)),
+        _ => throw_runtime_failure(ctx.program,
+        &format!("undefined method '{}' for process status", name), line),
+    }
+}
+
+fn describe_status(status: &ExitStatus) -> String {
+    match status.code() {
+        Some(ec) => format!("Exited with code {}", ec),
+        None => describe_signal(signal_of(status).unwrap_or(0)),
+    }
+}
+
+#[cfg(unix)]
+fn signal_of(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal_of(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+fn describe_signal(signal: i32) -> String {
+    let name = match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return format!("Killed by signal {}", signal),
+    };
+    format!("Killed by signal {} ({})", signal, name)
+}
+
+fn execute_task(task: &Task, tasks: &[Task], args: &[(String, String)], ctx: &mut Context) {
+    if !task_needed(task, tasks) {
+        return;
+    }
+    if ctx.opts.trace {
+        let dry_run = if ctx.opts.dry_run { "(dry run) " } else { "" };
+        eprintln!("** Execute {}{}", dry_run, task.get_name());
+    }
+    if ctx.opts.dry_run {
+        return;
+    }
+    ctx.task = task.get_name().to_owned();
+    if task.get_kind() == Kind::Directory {
+        make_dirs(task.get_name(), task.get_line(), ctx);
+    }
+    for action in task.get_actions() {
+        // A block's first parameter is the task itself, e.g. `do |t|`.
+        ctx.task_param = action.params.first().cloned().unwrap_or_default();
+        if !ctx.task_param.is_empty() {
+            let name = task.get_name().to_owned();
+            let param = ctx.task_param.clone();
+            ctx.set_var(&param, Value::Str(name));
         }
-        p = Regex::new("(.*)=.*Struct.new(((.*)))").unwrap();
-        for cap in p.captures_iter(&l) {
-            if !rstructs.contains(&cap[1].trim().to_owned()) || in_block {
-                let fields: Vec<String> = get_struct_fields(&cap[2].trim());
-                let s = Struct::new(&cap[1].trim(), fields);
-                structs.push(s.clone());
-                rstructs.push(cap[1].trim().to_owned());
-                continue;
+        // The second is its arguments, e.g. `do |t, args|`.
+        ctx.args_param = action.params.get(1).cloned().unwrap_or_default();
+        ctx.args = args.to_vec();
+        for stmt in &action.body {
+            if ctx.flow != Flow::Normal {
+                break;
             }
-        }
-        p = Regex::new("(.*)=.*\"(.*)\"").unwrap();
-        for cap in p.captures_iter(&l) {
-            if !rvars.contains(&cap[1].trim().to_owned()) || in_block {
-                let v = Variable::new(&cap[1].trim(), &cap[2].trim());
-                vars.push(v.clone());
-                rvars.push(v.get_key());
-                continue;
+            let value = eval_node(stmt, ctx);
+            if let NodeKind::Call { ref recv, ref name, .. } = stmt.kind {
+                // As a statement of a task, Dir.pwd prints out the working directory.
+                if qualified_name(recv, name).unwrap_or_default() == "Dir.pwd" {
+                    println!("{}", prefixed(ctx, &value.to_s()));
+                }
             }
         }
-        p = Regex::new(&format!("ENV{}\'(.*)\'{}",
-        regex::escape("["), regex::escape("]"))).unwrap();
-        for cap in p.captures_iter(&l) {
-            if !rvars.contains(&format!("ENV_{}", cap[1].trim())) || in_block {
-                let mut v = Variable::new(&format!("ENV_{}", &cap[1].trim()), "[ENV]");
-                vars.push(v.clone());
-                match env::var(&cap[1].trim()) {
-                    Ok(val) => v.set_value(&val),
-                    Err(e) => v.set_value(""),
+        ctx.flow = Flow::Normal;
+    }
+}
+
+/* Invokes prerequisites and then the task, as Rake does. The prerequisites of a
+   multitask, or of any task when running with --jobs, are shared among worker threads.
+   Each task takes the arguments it declares from those it was invoked with. */
+fn invoke_task(name: &str, args: &[(String, String)], tasks: &[Task], ctx: &mut Context, jobs: &Jobs) {
+    let task = tasks.iter().find(|t| t.get_name() == name).unwrap();
+    let args: Vec<(String, String)> = args.iter()
+    .filter(|(k, _)| task.get_arg_names().contains(k))
+    .cloned()
+    .collect();
+    let args = args.as_slice();
+    if !jobs.claim(name) {
+        if ctx.opts.trace {
+            eprintln!("** Invoke {}", name);
+        }
+        return;
+    }
+    if ctx.opts.trace {
+        let needed = if task_needed(task, tasks) { "" } else { ", not_needed" };
+        eprintln!("** Invoke {} (first_time{})", name, needed);
+    }
+    let depends = task.get_depends();
+    if depends.len() > 1 && (ctx.opts.jobs > 1 || task.get_kind() == Kind::Multi) {
+        thread::scope(|s| {
+            for d in depends {
+                if jobs.take_worker() {
+                    let mut tctx = ctx.clone();
+                    s.spawn(move || {
+                        invoke_task(d, args, tasks, &mut tctx, jobs);
+                        jobs.release_worker();
+                    });
+                } else {
+                    invoke_task(d, args, tasks, ctx, jobs);
                 }
-                vars.push(v.clone());
-                rvars.push(v.get_key());
-                continue;
             }
+        });
+    } else {
+        for d in depends {
+            invoke_task(d, args, tasks, ctx, jobs);
         }
-        p = Regex::new("(.*)=(.*).new(((.*)))").unwrap();
-        for cap in p.captures_iter(&l) {
-            for s in &mut structs {
-                if s.get_name() == cap[2].trim().to_owned() {
-                    s.set_variable(cap[1].trim());
-                    s.set_values(get_struct_values(cap[3].trim()));
-                    if !rvars.contains(&s.get_variable().to_owned()) {
-                        let v = Variable::new(s.get_variable(), "[Struct]");
-                        vars.push(v.clone());
-                        rvars.push(v.get_key());
-                    }
+    }
+    execute_task(task, tasks, args, ctx);
+    jobs.finish(name);
+}
+
+/* Methods on the task passed to an action block: */
+fn task_method(name: &str, line: usize, ctx: &mut Context) -> Value {
+    let task = ctx.tasks.iter().find(|t| t.get_name() == ctx.task).cloned();
+    match (name, task) {
+        ("name", _) | ("to_s", _) => Value::Str(ctx.task.clone()),
+        ("source", Some(task)) => task.get_depends().first().cloned().map(Value::Str).unwrap_or(Value::Nil),
+        ("prerequisites", Some(task)) | ("sources", Some(task)) => {
+            Value::Array(task.get_depends().iter().cloned().map(Value::Str).collect())
+        },
+        _ => throw_runtime_failure(ctx.program,
+        &format!("undefined method '{}' for task '{}'", name, ctx.task), line),
+    }
+}
+
+/* Methods on the arguments passed to an action block. Like Rake, any other
+   method looks up the argument of that name, which is nil when not given. */
+fn args_method(name: &str, args: &[Node], ctx: &mut Context) -> Value {
+    match (name, args.first().map(|a| &a.kind)) {
+        ("with_defaults", Some(NodeKind::Hash(pairs))) => {
+            for (key, value) in pairs {
+                let key = eval_node(key, ctx).to_s();
+                let value = eval_node(value, ctx).to_s();
+                if !ctx.args.iter().any(|(k, _)| *k == key) {
+                    ctx.args.push((key, value));
                 }
             }
-            continue;
+            Value::Nil
+        },
+        _ => arg_value(name, ctx),
+    }
+}
+
+fn arg_value(name: &str, ctx: &Context) -> Value {
+    ctx.args.iter().find(|(k, _)| k == name).map(|(_, v)| Value::Str(v.clone())).unwrap_or(Value::Nil)
+}
+
+/* A block parameter hides the task, its arguments or an sh status of the same name,
+   e.g. `|t|` within `do |t|`. Returns the names to restore once the block is done. */
+fn shadow_params(params: &[String], ctx: &mut Context) -> (String, String, String) {
+    let saved = (ctx.task_param.clone(), ctx.args_param.clone(), ctx.status_param.clone());
+    for name in [&mut ctx.task_param, &mut ctx.args_param, &mut ctx.status_param] {
+        if params.contains(name) {
+            name.clear();
         }
-        p = Regex::new("task :.* do #[ignore]").unwrap();
-        if p.is_match(&l) {
-            continue;
+    }
+    saved
+}
+
+fn restore_params(saved: (String, String, String), ctx: &mut Context) {
+    ctx.task_param = saved.0;
+    ctx.args_param = saved.1;
+    ctx.status_param = saved.2;
+}
+
+/* Runs a block once per value, bound to its first parameter, until a `break`.
+   Like Ruby, a block with several parameters takes an array apart, e.g. a hash
+   entry for `|key, value|`. Returns whether the block broke out. */
+fn iterate<I>(values: I, block: &Option<Block>, name: &str, line: usize, ctx: &mut Context) -> bool
+where I: IntoIterator<Item = Value> {
+    let block = match *block {
+        Some(ref block) => block,
+        None => throw_runtime_failure(ctx.program,
+        &format!("no block given for '{}'", name), line),
+    };
+    let saved: Vec<Option<Value>> = block.params.iter().map(|p| ctx.get_var(p)).collect();
+    let shadowed = shadow_params(&block.params, ctx);
+    let mut broke = false;
+    for value in values {
+        match (block.params.len(), &value) {
+            (0, _) => {},
+            (1, _) => ctx.set_var(&block.params[0], value.clone()),
+            (_, Value::Array(items)) => {
+                for (i, param) in block.params.iter().enumerate() {
+                    ctx.set_var(param, items.get(i).cloned().unwrap_or(Value::Nil));
+                }
+            },
+            _ => {
+                ctx.set_var(&block.params[0], value.clone());
+                for param in &block.params[1..] {
+                    ctx.set_var(param, Value::Nil);
+                }
+            },
         }
-        p = Regex::new("task :(.*) do").unwrap();
-        for cap in p.captures_iter(&l) {
-            name = cap[1].to_owned();
-            depends = String::new();
-            continue;
+        exec_stmts(&block.body, ctx);
+        if ctx.flow == Flow::Break {
+            ctx.flow = Flow::Normal;
+            broke = true;
+            break;
         }
-        p = Regex::new(&format!("task :(.*) => {}*:*(.*){} do",
-        regex::escape("["), regex::escape("]"))).unwrap();
-        if p.is_match(&l) {
-            for cap in p.captures_iter(&l) {
-                name = cap[1].to_owned();
-                depends = cap[2].to_owned();
-                continue;
+        if ctx.flow != Flow::Normal {
+            broke = true;
+            break;
+        }
+    }
+    // Block parameters do not outlive the block.
+    for (param, value) in block.params.iter().zip(saved) {
+        match value {
+            Some(value) => ctx.set_var(param, value),
+            None => ctx.vars.retain(|v| v.get_key() != *param),
+        }
+    }
+    restore_params(shadowed, ctx);
+    broke
+}
+
+/* The leading number of a string, as String#to_i and #to_f read it: */
+fn leading_number(s: &str, float: bool) -> Value {
+    let p = if float {
+        Regex::new(r"^\s*[-+]?\d+(\.\d+)?([eE][-+]?\d+)?").unwrap()
+    } else {
+        Regex::new(r"^\s*[-+]?\d+").unwrap()
+    };
+    let number = p.find(s).map(|m| m.as_str().trim()).unwrap_or("0");
+    if float {
+        Value::Float(number.parse().unwrap_or(0.0))
+    } else {
+        Value::Int(number.parse().unwrap_or(0))
+    }
+}
+
+/* Methods on values, including the iterators that take a block: */
+fn value_method(value: Value, name: &str, params: &[Value], block: &Option<Block>,
+line: usize, ctx: &mut Context) -> Value {
+    let undefined = |ctx: &Context, value: &Value| -> ! {
+        throw_runtime_failure(ctx.program,
+        &format!("undefined method '{}' for {}", name, value.type_name()), line)
+    };
+    let arg = params.first().cloned().unwrap_or(Value::Nil);
+    match (name, &value) {
+        ("each", Value::Array(items)) => {
+            iterate(items.iter().cloned(), block, name, line, ctx);
+            value.clone()
+        },
+        ("each", Value::Hash(pairs)) => {
+            let entries = pairs.iter().map(|(k, v)| Value::Array(vec![k.clone(), v.clone()]));
+            iterate(entries, block, name, line, ctx);
+            value.clone()
+        },
+        ("times", &Value::Int(n)) => {
+            iterate((0..n).map(Value::Int), block, name, line, ctx);
+            value.clone()
+        },
+        ("upto", &Value::Int(from)) | ("downto", &Value::Int(from)) => {
+            let to = match arg {
+                Value::Int(to) => to,
+                _ => throw_runtime_failure(ctx.program,
+                &format!("comparison of Integer with {} failed", arg.inspect()), line),
+            };
+            // The values are made as the block asks for them, so `break` ends a long range early.
+            if name == "downto" {
+                iterate((to..=from).rev().map(Value::Int), block, name, line, ctx);
+            } else {
+                iterate((from..=to).map(Value::Int), block, name, line, ctx);
+            }
+            value.clone()
+        },
+        ("to_s", _) => Value::Str(value.to_s()),
+        ("inspect", _) => Value::Str(value.inspect()),
+        ("to_json", _) => Value::Str(value.to_json()),
+        ("nil?", _) => Value::Bool(value == Value::Nil),
+        ("to_a", &Value::Nil) => Value::Array(Vec::new()),
+        ("to_a", &Value::Array(_)) => value.clone(),
+        ("to_a", Value::Hash(pairs)) => {
+            Value::Array(pairs.iter().map(|(k, v)| Value::Array(vec![k.clone(), v.clone()])).collect())
+        },
+        ("to_h", &Value::Hash(_)) => value.clone(),
+        ("to_h", &Value::Nil) => Value::Hash(Vec::new()),
+        ("to_i", &Value::Nil) => Value::Int(0),
+        ("to_i", &Value::Int(_)) => value.clone(),
+        ("to_i", &Value::Float(f)) => Value::Int(f.trunc() as i64),
+        ("to_i", Value::Str(s)) => leading_number(s, false),
+        ("to_f", &Value::Nil) => Value::Float(0.0),
+        ("to_f", &Value::Int(n)) => Value::Float(n as f64),
+        ("to_f", &Value::Float(_)) => value.clone(),
+        ("to_f", Value::Str(s)) => leading_number(s, true),
+        ("to_sym", &Value::Str(ref s)) | ("to_sym", &Value::Sym(ref s)) => Value::Sym(s.clone()),
+        ("abs", &Value::Int(_)) | ("abs", &Value::Float(_)) | ("succ", &Value::Int(_)) => {
+            match value.unary(name) {
+                Ok(value) => value,
+                Err(message) => throw_runtime_failure(ctx.program, &message, line),
             }
+        },
+        ("length", &Value::Str(ref s)) | ("size", &Value::Str(ref s)) => Value::Int(s.chars().count() as i64),
+        ("length", &Value::Array(ref items)) | ("size", &Value::Array(ref items)) => Value::Int(items.len() as i64),
+        ("length", &Value::Hash(ref pairs)) | ("size", &Value::Hash(ref pairs)) => Value::Int(pairs.len() as i64),
+        ("empty?", Value::Str(s)) => Value::Bool(s.is_empty()),
+        ("empty?", Value::Array(items)) => Value::Bool(items.is_empty()),
+        ("empty?", Value::Hash(pairs)) => Value::Bool(pairs.is_empty()),
+        ("upcase", Value::Str(s)) => Value::Str(s.to_uppercase()),
+        ("downcase", Value::Str(s)) => Value::Str(s.to_lowercase()),
+        ("strip", Value::Str(s)) => Value::Str(s.trim().to_owned()),
+        ("reverse", Value::Str(s)) => Value::Str(s.chars().rev().collect()),
+        ("reverse", Value::Array(items)) => Value::Array(items.iter().rev().cloned().collect()),
+        ("include?", Value::Str(s)) => Value::Bool(s.contains(&arg.to_s())),
+        ("include?", Value::Array(items)) => Value::Bool(items.iter().any(|i| i.equals(&arg))),
+        ("first", Value::Array(items)) => items.first().cloned().unwrap_or(Value::Nil),
+        ("last", Value::Array(items)) => items.last().cloned().unwrap_or(Value::Nil),
+        ("join", Value::Array(items)) => {
+            let items: Vec<String> = items.iter().map(|i| i.to_s()).collect();
+            Value::Str(items.join(&arg.to_s()))
+        },
+        ("keys", Value::Hash(pairs)) => Value::Array(pairs.iter().map(|(k, _)| k.clone()).collect()),
+        ("values", Value::Hash(pairs)) => Value::Array(pairs.iter().map(|(_, v)| v.clone()).collect()),
+        ("key?", &Value::Hash(ref pairs)) | ("has_key?", &Value::Hash(ref pairs)) => {
+            Value::Bool(pairs.iter().any(|(k, _)| k.equals(&arg)))
+        },
+        _ => undefined(ctx, &value),
+    }
+}
+
+/* Runs a `def` method with its own local variables, as in Ruby. The value is
+   that of an explicit `return`, or else of the last statement. */
+fn call_method(method: &Method, args: &[Value], line: usize, ctx: &mut Context) -> Value {
+    let params = method.get_params();
+    if args.len() < method.get_required() || args.len() > params.len() {
+        let expected = if method.get_required() == params.len() {
+            params.len().to_string()
         } else {
-            p = Regex::new(&format!("task :(.*) => {}*:*(.*){}",
-            regex::escape("["), regex::escape("]"))).unwrap();
-            for cap in p.captures_iter(&l) {
-                name = cap[1].to_owned();
-                depends = cap[2].to_owned();
-                tasks.push(Task::new(&name, &depends, "", "", i));
-                continue;
+            format!("{}..{}", method.get_required(), params.len())
+        };
+        throw_runtime_failure(ctx.program, &format!("wrong number of arguments for {} (given {}, expected {})",
+        method.get_name(), args.len(), expected), line);
+    }
+    let outer = mem::take(&mut ctx.vars);
+    // Nor does the body see the task, its arguments or an sh status by the caller's names.
+    let task_param = mem::take(&mut ctx.task_param);
+    let args_param = mem::take(&mut ctx.args_param);
+    let status_param = mem::take(&mut ctx.status_param);
+    for (i, (param, default)) in params.iter().enumerate() {
+        let value = match (args.get(i), default) {
+            (Some(arg), _) => arg.clone(),
+            (None, Some(default)) => eval_node(default, ctx),
+            (None, None) => unreachable!(),
+        };
+        ctx.set_var(param, value);
+    }
+    let last = exec_stmts(method.get_body(), ctx);
+    ctx.vars = outer;
+    ctx.task_param = task_param;
+    ctx.args_param = args_param;
+    ctx.status_param = status_param;
+    match mem::replace(&mut ctx.flow, Flow::Normal) {
+        Flow::Return(value) => value,
+        _ => last,
+    }
+}
+
+/* What puts prints for a value: arrays one element per line, nil as an empty line,
+   and a string's own trailing newline not doubled. */
+fn puts_lines(value: &Value, lines: &mut Vec<String>) {
+    match *value {
+        Value::Array(ref items) if !items.is_empty() => {
+            for item in items {
+                puts_lines(item, lines);
             }
-        }
-        p = Regex::new("(puts) \"(.*)\"").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            params = cap[2].to_owned();
-            tasks.push(Task::new(&name, &depends, &command, &params, i));
-            l_puts = true;
-            continue;
-        }
-        p = Regex::new("(puts)").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            params = String::new();
-            if !l_puts {
-                tasks.push(Task::new(&name, &depends, &command, &params, i));
+        },
+        Value::Array(_) => {},
+        _ => {
+            let s = value.to_s();
+            lines.push(s.strip_suffix('\n').unwrap_or(&s).to_owned());
+        },
+    }
+}
+
+fn eval_call(recv: &Option<Box<Node>>, name: &str, args: &[Node],
+block: &Option<Block>, line: usize, ctx: &mut Context) -> Value {
+    let qname = match qualified_name(recv, name) {
+        Some(qname) => qname,
+        None => {
+            let recv = recv.as_ref().unwrap();
+            if let NodeKind::Ident(ref v) = recv.kind {
+                if *v == ctx.task_param {
+                    return task_method(name, line, ctx);
+                }
+                if *v == ctx.args_param {
+                    return args_method(name, args, ctx);
+                }
+                if *v == ctx.status_param && ctx.status.is_some() {
+                    return status_method(name, line, ctx);
+                }
             }
-            continue;
+            let value = eval_node(recv, ctx);
+            let values: Vec<Value> = args.iter().map(|a| eval_node(a, ctx)).collect();
+            return value_method(value, name, &values, block, line, ctx);
+        },
+    };
+    let mut values: Vec<Value> = Vec::new();
+    if !["task", "multitask", "file", "directory", "rule"].contains(&qname.as_str()) {
+        for arg in args {
+            values.push(eval_node(arg, ctx));
         }
-        p = Regex::new("(puts) (.*).to_h.to_json").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            params = cap[2].to_owned();
-            params = format!("#{{{}}}", &params);
-            tasks.push(Task::new(&name, &depends, &command, &params.trim(), i));
-            l_puts = true;
-            continue;
+    }
+    if recv.is_none() {
+        if let Some(method) = ctx.methods.iter().find(|m| m.get_name() == name).cloned() {
+            return call_method(&method, &values, line, ctx);
         }
-        /*p = Regex::new(".write.*(.*)").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = "File.write".to_owned();
-            params = format!("{}", &cap[1]); 
-            tasks.push(Task::new(&name, &depends, &command, &params.trim(), i));
-            continue;
-        }*/
-        p = Regex::new("(sleep) (.*)").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            params = cap[2].to_owned();
-            tasks.push(Task::new(&name, &depends, &command, &params.trim(), i));
-        }
-        p = Regex::new("(Dir.pwd)").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            tasks.push(Task::new(&name, &depends, &command, "", i));
-        }
-        p = Regex::new("(Dir.chdir).*\"(.*)\"").unwrap();
-        if p.is_match(&l) {
-            for cap in p.captures_iter(&l) {
-                command = cap[1].to_owned();
-                params = cap[2].to_owned();
-                tasks.push(Task::new(&name, &depends, &command, &params, i));
-                continue;
+    }
+    let params: Vec<String> = values.iter().map(|v| v.to_s()).collect();
+    match qname.as_str() {
+        "require" => Value::Bool(true),
+        "import" => {
+            // Imported files are loaded once the current file is done.
+            for file in params {
+                ctx.imports.push((file, line));
             }
-        } else {
-            p = Regex::new("(Dir.chdir)(((.*)))").unwrap();
-            for cap in p.captures_iter(&l) {
-                command = cap[1].to_owned();
-                params = cap[2].to_owned();
-                params = format!("#{{{}}}", &params[1..params.len() - 2]);
-                tasks.push(Task::new(&name, &depends, &command, &params, i));
-            }
-        }
-        p = Regex::new("(sh) \"(.*)\"").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            params = cap[2].to_owned();
-            tasks.push(Task::new(&name, &depends, &command, &params, i));
-        }
-        p = Regex::new("(ruby) \"(.*)\"").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = "sh".to_owned();
-            params = format!("{} {}", cap[1].to_owned(), cap[2].to_owned());
-            tasks.push(Task::new(&name, &depends, &command, &params, i));
-        }
-        p = Regex::new("(File.delete).*\"(.*)\"").unwrap();
-        if p.is_match(&l) {
-            for cap in p.captures_iter(&l) {
-                command = cap[1].to_owned();
-                params = cap[2].to_owned();
-                tasks.push(Task::new(&name, &depends, &command, &params, i));
-                continue;
+            Value::Nil
+        },
+        "load" => {
+            for file in &params {
+                if !Path::new(file).is_file() {
+                    throw_runtime_failure(ctx.program,
+                    &format!("cannot load such file -- {}", file), line);
+                }
+                load_rakefile(file, ctx);
             }
-        } else {
-            p = Regex::new("(File.delete)(((.*)))").unwrap();
-            for cap in p.captures_iter(&l) {
-                command = cap[1].to_owned();
-                params = cap[2].to_owned();
-                params = format!("#{{{}}}", &params[1..params.len() - 2]);
-                tasks.push(Task::new(&name, &depends, &command, &params, i));
-            }
-        }
-        p = Regex::new(r"(FileUtils.copy)(((.*)))").unwrap();
-        for cap in p.captures_iter(&l) {
-            command = cap[1].to_owned();
-            params = cap[2].to_owned();
-            let ip = Regex::new("(.*), (.*)").unwrap();
-            let mut fparams = String::new();
-            for cap in ip.captures_iter(&params) {
-                fparams = format!("#{{{}}} #{{{}}}", &cap[1][1..cap[1].len()], 
-                &cap[2][0..cap[2].len() - 2]);
-            }
-            tasks.push(Task::new(&name, &depends, &command, &fparams, i));
-
-        }
-        p = Regex::new(&format!("if OS.windows{} then", regex::escape("?"))).unwrap();
-        if p.is_match(&l) {
-            if get_os() == "Unknown" {
-                in_block = true;
+            Value::Bool(true)
+        },
+        "desc" => {
+            // Describes the next task to be defined.
+            ctx.desc = params.join(" ");
+            Value::Nil
+        },
+        "task" => Value::Str(define_task(Kind::Normal, args, block, line, ctx)),
+        "multitask" => Value::Str(define_task(Kind::Multi, args, block, line, ctx)),
+        "file" => Value::Str(define_task(Kind::File, args, block, line, ctx)),
+        "rule" => {
+            define_rule(args, block, line, ctx);
+            Value::Nil
+        },
+        "namespace" => {
+            let ns = match params.first() {
+                Some(ns) if !ns.is_empty() => ns.clone(),
+                _ => throw_runtime_failure(ctx.program, "namespace requires a name", line),
+            };
+            ctx.scope.push(ns);
+            if let Some(block) = block {
+                exec_stmts(&block.body, ctx);
+            }
+            ctx.scope.pop();
+            Value::Nil
+        },
+        "directory" => {
+            let dir = define_task(Kind::Directory, args, block, line, ctx);
+            define_parent_dirs(&dir, line, ctx);
+            Value::Str(dir)
+        },
+        "puts" => {
+            let mut lines: Vec<String> = Vec::new();
+            for value in &values {
+                puts_lines(value, &mut lines);
+            }
+            if values.is_empty() {
+                lines.push(String::new());
+            }
+            if ctx.opts.verbose {
+                for l in &lines {
+                    println!("{}", prefixed(ctx, l));
+                }
+            }
+            Value::Nil
+        },
+        "loop" => {
+            // Runs until a `break`.
+            while !iterate(iter::once(Value::Nil), block, name, line, ctx) {}
+            Value::Nil
+        },
+        "sleep" => {
+            let ms = params.first().map(|p| parse_unit(p)).unwrap_or(0);
+            thread::sleep(time::Duration::from_millis(ms as u64));
+            Value::Int(ms as i64)
+        },
+        "sh" => {
+            let status = run_sh(&params, line, ctx);
+            match *block {
+                Some(ref b) => sh_block(b, status, ctx),
+                None => check_status(&status, line, ctx),
+            }
+            Value::Bool(status.success())
+        },
+        "ruby" => {
+            let argv = if params.len() == 1 {
+                vec![format!("ruby {}", params[0])]
             } else {
-                in_block = false;
-            }
-        }
-        p = Regex::new(&format!("if ENV{}\'(.*)\'{} then",
-        regex::escape("["), regex::escape("]"))).unwrap();
-        for cap in p.captures_iter(&l) {
-            for rvar in &rvars {
-                for var in &vars {
-                    if &var.get_key() == rvar {
-                        if var.get_value().len() > 0 {
-                            in_block = true;
-                            break;
-                        } else if var.get_value().len() == 0 {
-                            in_block = false;
-                            break;
-                        }
+                let mut argv = vec!["ruby".to_owned()];
+                argv.extend(params);
+                argv
+            };
+            let status = run_sh(&argv, line, ctx);
+            match *block {
+                Some(ref b) => sh_block(b, status, ctx),
+                None => check_status(&status, line, ctx),
+            }
+            Value::Bool(status.success())
+        },
+        "Dir.pwd" => Value::Str(format!("{}", ctx.wkdir.display())),
+        "Dir.chdir" => {
+            let dir = params.first().cloned().unwrap_or_default();
+            if env::set_current_dir(&dir).is_err() {
+                throw_runtime_failure(ctx.program,
+                &format!("No such file or directory - {}", dir), line);
+            }
+            ctx.wkdir = env::current_dir().unwrap();
+            Value::Int(0)
+        },
+        "File.delete" => {
+            for file in &params {
+                if Path::new(file).exists() {
+                    if let Err(e) = fs::remove_file(file) {
+                        throw_runtime_failure(ctx.program, &io_message(&e, file), line);
                     }
                 }
             }
+            Value::Int(params.len() as i64)
+        },
+        "mkdir_p" | "FileUtils.mkdir_p" => {
+            for dir in &params {
+                make_dirs(dir, line, ctx);
+            }
+            Value::Array(values)
+        },
+        "File.exist?" | "File.exists?" => {
+            Value::Bool(params.first().map(|f| Path::new(f).exists()).unwrap_or(false))
+        },
+        "File.write" => {
+            if params.len() < 2 {
+                throw_runtime_failure(ctx.program,
+                "wrong number of arguments for File.write (expected 2)", line);
+            }
+            let written = File::create(&params[0]).and_then(|mut w| w.write_all(params[1].as_bytes()));
+            if let Err(e) = written {
+                throw_runtime_failure(ctx.program, &io_message(&e, &params[0]), line);
+            }
+            Value::Int(params[1].len() as i64)
+        },
+        "FileUtils.copy" | "FileUtils.cp" => {
+            if params.len() < 2 {
+                throw_runtime_failure(ctx.program,
+                &format!("wrong number of arguments for {} (expected 2)", qname), line);
+            }
+            if let Err(e) = fs::copy(&params[0], &params[1]) {
+                let file = if Path::new(&params[0]).exists() { &params[1] } else { &params[0] };
+                throw_runtime_failure(ctx.program, &io_message(&e, file), line);
+            }
+            Value::Nil
+        },
+        "OS.windows?" => Value::Bool(cfg!(windows)),
+        "OS.mac?" | "OS.osx?" => Value::Bool(cfg!(target_os = "macos")),
+        "OS.linux?" => Value::Bool(cfg!(target_os = "linux")),
+        "OS.posix?" | "OS.unix?" => Value::Bool(cfg!(unix)),
+        _ => {
+            // An instance of a Struct defined in the Rakefile.
+            let definition = ctx.structs.iter()
+            .find(|s| format!("{}.new", s.get_name()) == qname).cloned();
+            match definition {
+                Some(s) => s.instance(values),
+                None => throw_runtime_failure(ctx.program,
+                &format!("undefined method '{}'", qname), line),
+            }
+        },
+    }
+}
+
+fn eval_assign(target: &Node, value: &Node, ctx: &mut Context) -> Value {
+    let name = match target.kind {
+        NodeKind::Ident(ref name) | NodeKind::Const(ref name) => name.clone(),
+        _ => unreachable!(),
+    };
+    if let NodeKind::Call { ref recv, name: ref method, ref args, .. } = value.kind {
+        if qualified_name(recv, method).unwrap_or_default() == "Struct.new" {
+            let fields: Vec<String> = args.iter().map(|a| eval_node(a, ctx).to_s()).collect();
+            ctx.structs.push(Struct::new(&name, fields));
+            return Value::Str(name);
         }
     }
+    let v = eval_node(value, ctx);
+    ctx.set_var(&name, v.clone());
+    v
+}
 
-    let pvars = process_struct_vars(&structs, process_vars(rvars, vars));
-    // println!("Vars = {:#?}", pvars);
-    let mut ptasks: Vec<Task> = Vec::new();
-    for task in &tasks {
-        let ptask = parse_vars_in_task(&task, &pvars);
-        ptasks.push(ptask);
-    }
-    //println!("Tasks = {:#?}", ptasks);
-
-    let mut matched = false;
-    let mut qtask = String::new();
-    let mut rtasks: Vec<Task> = Vec::new();
-    let mut lnos: Vec<usize> = Vec::new();
-    for stask in stasks {
-        qtask = stask.to_owned();
-        for task in ptasks.clone() {
-            if task.get_name() == stask {
-                matched = true;
-                let depends = task.get_depends();
-                if !depends.is_empty() {
-                    for dtask in ptasks.clone() {
-                        if dtask.get_name() == depends 
-                        && !lnos.contains(&dtask.get_line()) {
-                            rtasks.push(dtask.clone());
-                            lnos.push(dtask.get_line());
-                        }
-                    }
+fn eval_node(node: &Node, ctx: &mut Context) -> Value {
+    let line = node.span.line;
+    match node.kind {
+        NodeKind::Str(ref parts) => Value::Str(interpolate(parts, ctx)),
+        NodeKind::Int(n) => Value::Int(n),
+        NodeKind::Float(f) => Value::Float(f),
+        NodeKind::Bool(b) => Value::Bool(b),
+        NodeKind::Nil => Value::Nil,
+        NodeKind::Sym(ref s) => Value::Sym(s.clone()),
+        NodeKind::Regex(ref r) => Value::Str(r.clone()),
+        NodeKind::Array(ref items) => Value::Array(items.iter().map(|i| eval_node(i, ctx)).collect()),
+        NodeKind::Hash(ref pairs) => {
+            let mut hash: Vec<(Value, Value)> = Vec::new();
+            for (key, value) in pairs {
+                let key = eval_node(key, ctx);
+                let value = eval_node(value, ctx);
+                match hash.iter_mut().find(|(k, _)| k.equals(&key)) {
+                    Some(pair) => pair.1 = value,
+                    None => hash.push((key, value)),
+                }
+            }
+            Value::Hash(hash)
+        },
+        NodeKind::Ident(ref name) => match ctx.get_var(name) {
+            Some(value) => value,
+            None => eval_call(&None, name, &[], &None, line, ctx),
+        },
+        NodeKind::Const(ref name) => {
+            if !ctx.structs.iter().any(|s| s.get_name() == name) {
+                throw_runtime_failure(ctx.program,
+                &format!("uninitialized constant {}", name), line);
+            }
+            Value::Str(name.clone())
+        },
+        NodeKind::Assign(ref target, ref value) => eval_assign(target, value, ctx),
+        NodeKind::Index(ref recv, ref args) => {
+            match recv.kind {
+                NodeKind::Const(ref c) if c == "ENV" && args.len() == 1 => {
+                    let key = eval_node(&args[0], ctx).to_s();
+                    env::var(&key).map(Value::Str).unwrap_or(Value::Nil)
+                },
+                NodeKind::Ident(ref v) if *v == ctx.args_param && args.len() == 1 => {
+                    let key = eval_node(&args[0], ctx).to_s();
+                    arg_value(&key, ctx)
+                },
+                _ => {
+                    let value = eval_node(recv, ctx);
+                    let key = match args.first() {
+                        Some(arg) if args.len() == 1 => eval_node(arg, ctx),
+                        _ => throw_runtime_failure(ctx.program,
+                        "wrong number of arguments for [] (expected 1)", line),
+                    };
+                    eval_index(&value, &key, line, ctx)
+                },
+            }
+        },
+        NodeKind::Call { ref recv, ref name, ref args, ref block } => {
+            eval_call(recv, name, args, block, line, ctx)
+        },
+        NodeKind::If(ref cond, ref body, ref otherwise) => {
+            if eval_node(cond, ctx).truthy() {
+                exec_stmts(body, ctx)
+            } else {
+                exec_stmts(otherwise, ctx)
+            }
+        },
+        NodeKind::While(ref cond, ref body) => {
+            while eval_node(cond, ctx).truthy() {
+                exec_stmts(body, ctx);
+                if ctx.flow == Flow::Break {
+                    ctx.flow = Flow::Normal;
+                    break;
+                }
+                if ctx.flow != Flow::Normal {
+                    break;
                 }
-                rtasks.push(task.clone());
             }
+            Value::Nil
+        },
+        NodeKind::Break => {
+            ctx.flow = Flow::Break;
+            Value::Nil
+        },
+        NodeKind::Return(ref value) => {
+            let value = match *value {
+                Some(ref value) => eval_node(value, ctx),
+                None => Value::Nil,
+            };
+            ctx.flow = Flow::Return(value.clone());
+            value
+        },
+        NodeKind::Def(ref name, ref params, ref body) => {
+            ctx.methods.retain(|m| m.get_name() != name);
+            ctx.methods.push(Method::new(name, params.clone(), body.clone()));
+            Value::Sym(name.clone())
+        },
+        NodeKind::Not(ref operand) => Value::Bool(!eval_node(operand, ctx).truthy()),
+        NodeKind::Neg(ref operand) => {
+            match eval_node(operand, ctx).unary("-@") {
+                Ok(value) => value,
+                Err(message) => throw_runtime_failure(ctx.program, &message, line),
+            }
+        },
+        // Like Ruby, && and || give back whichever operand decided the result.
+        NodeKind::And(ref left, ref right) => {
+            let value = eval_node(left, ctx);
+            if value.truthy() { eval_node(right, ctx) } else { value }
+        },
+        NodeKind::Or(ref left, ref right) => {
+            let value = eval_node(left, ctx);
+            if value.truthy() { value } else { eval_node(right, ctx) }
+        },
+        NodeKind::Binary(ref op, ref left, ref right) => eval_binary(op, left, right, line, ctx),
+    }
+}
+
+/* Indexing arrays and strings by position (negative from the end), hashes by key: */
+fn eval_index(value: &Value, key: &Value, line: usize, ctx: &mut Context) -> Value {
+    let position = |len: usize, i: i64| -> Option<usize> {
+        let i = if i < 0 { len as i64 + i } else { i };
+        if i >= 0 && (i as usize) < len { Some(i as usize) } else { None }
+    };
+    match (value, key) {
+        (Value::Array(items), &Value::Int(i)) => {
+            position(items.len(), i).map(|i| items[i].clone()).unwrap_or(Value::Nil)
+        },
+        (Value::Str(s), &Value::Int(i)) => {
+            let chars: Vec<char> = s.chars().collect();
+            position(chars.len(), i).map(|i| Value::Str(chars[i].to_string())).unwrap_or(Value::Nil)
+        },
+        (&Value::Hash(_), _) => value.get(key),
+        (&Value::Nil, _) => throw_runtime_failure(ctx.program,
+        "undefined method '[]' for nil", line),
+        _ => throw_runtime_failure(ctx.program,
+        &format!("no implicit conversion of {} into Integer", key.type_name()), line),
+    }
+}
+
+fn eval_binary(op: &str, left: &Node, right: &Node, line: usize, ctx: &mut Context) -> Value {
+    let lvalue = eval_node(left, ctx);
+    let rvalue = eval_node(right, ctx);
+    if op == "=~" || op == "!~" {
+        // Like Ruby, =~ gives the position of the match, or nil.
+        let pattern = rvalue.to_s();
+        let p = match Regex::new(&pattern) {
+            Ok(p) => p,
+            Err(_) => throw_runtime_failure(ctx.program,
+            &format!("invalid regexp /{}/", pattern), line),
+        };
+        let found = p.find(&lvalue.to_s()).map(|m| m.start());
+        return match op {
+            "=~" => found.map(|i| Value::Int(i as i64)).unwrap_or(Value::Nil),
+            _ => Value::Bool(found.is_none()),
+        };
+    }
+    match lvalue.operate(op, &rvalue) {
+        Ok(value) => value,
+        Err(message) => throw_runtime_failure(ctx.program, &message, line),
+    }
+}
+
+fn exec_stmts(stmts: &[Node], ctx: &mut Context) -> Value {
+    let mut last = Value::Nil;
+    for stmt in stmts {
+        if ctx.flow != Flow::Normal {
+            break;
         }
+        last = eval_node(stmt, ctx);
+    }
+    last
+}
+
+/* Runs the top level of a Rakefile, or of a file it imports or loads. */
+fn load_rakefile(rakefile: &str, ctx: &mut Context) {
+    let mut rf = String::new();
+    let mut file = File::open(rakefile).unwrap();
+    let _ = file.read_to_string(&mut rf);
+    let program_ast = match parser::parse(&rf) {
+        Ok(program_ast) => program_ast,
+        Err(e) => throw_parse_failure(ctx.program, rakefile, &e),
+    };
+    let parent = mem::replace(&mut ctx.rakefile, rakefile.to_owned());
+    exec_stmts(&program_ast, ctx);
+    ctx.rakefile = parent;
+}
+
+/* Like Rake: the Rakefile first, then the .rake files in rakelib, then each `import`ed file once.
+   The system rakefiles are just the .rake files in their directory, with no Rakefile. */
+fn invoke_rakefile(program: &str, rakefile: &str, rakelib: &str,
+stasks: &[(String, Vec<String>)], opts: &Options) {
+    let mut ctx = Context {
+        program,
+        rakefile: String::new(),
+        imports: Vec::new(),
+        opts,
+        vars: Vec::new(),
+        structs: Vec::new(),
+        methods: Vec::new(),
+        tasks: Vec::new(),
+        rules: Vec::new(),
+        wkdir: env::current_dir().unwrap(),
+        task: String::new(),
+        desc: String::new(),
+        scope: Vec::new(),
+        task_param: String::new(),
+        args_param: String::new(),
+        args: Vec::new(),
+        status_param: String::new(),
+        status: None,
+        flow: Flow::Normal,
+        parallel: false,
+    };
+    if !rakefile.is_empty() {
+        load_rakefile(rakefile, &mut ctx);
     }
-    //println!("{:#?}", rtasks);
-    if !matched {
-        throw_no_task_failure(&program, &qtask);
+    let mut rakelib: Vec<PathBuf> = match fs::read_dir(rakelib) {
+        Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map(|e| e == "rake").unwrap_or(false))
+        .collect(),
+        Err(_) => Vec::new(),
+    };
+    rakelib.sort();
+    let mut loaded: Vec<String> = vec![rakefile.to_owned()];
+    for file in &rakelib {
+        let file = file.display().to_string();
+        if !loaded.contains(&file) {
+            loaded.push(file.clone());
+            load_rakefile(&file, &mut ctx);
+        }
     }
-    let mut wkdir = env::current_dir().unwrap();
-    for task in &rtasks {
-        match task.get_command() {
-            "puts" => if opts.verbose { println!("{}", task.get_params()) },
-            "sleep" => { 
-                thread::sleep(
-                time::Duration::from_millis(parse_unit(task.get_params()) as u64)); 
-            },
-            "sh" => {
-                if opts.verbose {
-                    println!("{}", task.get_params());
-                }
-                let split = task.get_params().split(" ");
-                let mut args: Vec<&str> = split.collect();
-                let cmd = args[0]; args.remove(0);
-                let mut output = Command::new(&cmd)
-                .args(&args)
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .current_dir(&format!("{}", wkdir.display()))
-                .spawn()
-                .unwrap();
-                let status = output.wait();
-                let strstat = format!("{:?}", status);
-                let p = Regex::new("([0-9]+)").unwrap();
-                let mut code = String::new();
-                for cap in p.captures_iter(&strstat) {
-                    code = cap[0].to_owned();
-                }
-                let ec = parse_unit(code.trim());
-                if opts.exit_codes {
-                    println!("Exited with code {}", ec);
-                }
-                if ec != 0 && !opts.ignore {
-                    throw_build_failiure(&program, &qtask, ec, task.get_line());
-                }
-            },
-            "Dir.pwd" => {
-                println!("{}", wkdir.display());
-            },
-            "Dir.chdir" => {
-                assert!(env::set_current_dir(&task.get_params()).is_ok());
-                wkdir = env::current_dir().unwrap();
-            },
-            "File.delete" => {
-                let file = &task.get_params();
-                if Path::new(file).exists() {
-                    fs::remove_file(file).unwrap()
-                }
-            },
-            "FileUtils.copy" => {
-                let sd = &task.get_params();
-                let p = Regex::new("(.*) (.*)").unwrap();
-                for cap in p.captures_iter(&sd) {
-                    fs::copy(&cap[1], &cap[2]).unwrap();
-                }
-            },
-            "File.write" => {
-                let c = &task.get_params();
-                let mut w = File::create("file").unwrap();
-                let _ = w.write_all("foo".as_bytes());
-            },
-            _ => {},
+    let mut i = 0;
+    while i < ctx.imports.len() {
+        let (file, line) = ctx.imports[i].clone();
+        i += 1;
+        if loaded.contains(&file) {
+            continue;
+        }
+        if !Path::new(&file).is_file() {
+            throw_runtime_failure(program, &format!("cannot load such file -- {}", file), line);
+        }
+        loaded.push(file.clone());
+        load_rakefile(&file, &mut ctx);
+    }
+    if opts.display != Display::Run && opts.display != Display::Dot && opts.display != Display::Json {
+        display_tasks(program, &ctx.tasks, opts);
+    }
+
+    let mut res = Resolution {
+        chain: Vec::new(),
+        invoked: Vec::new(),
+        tasks: Vec::new(),
+    };
+    let names: Vec<String> = stasks.iter()
+    .map(|(stask, _)| resolve_task(stask, None, &mut ctx, &mut res))
+    .collect();
+    if opts.display == Display::Dot || opts.display == Display::Json {
+        display_graph(&res.tasks, opts);
+    }
+    ctx.parallel = opts.jobs > 1 || res.tasks.iter().any(|t| t.get_kind() == Kind::Multi);
+    // Like Rake, multitasks default to the number of CPU cores + 4.
+    let workers = match opts.jobs {
+        0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1) + 4,
+        n => n,
+    };
+    // The calling thread is one of the workers.
+    let jobs = Jobs::new(workers - 1);
+    for (name, (_, values)) in names.iter().zip(stasks) {
+        let task = res.tasks.iter().find(|t| t.get_name() == name).unwrap();
+        let args: Vec<(String, String)> = task.get_arg_names().iter().cloned()
+        .zip(values.iter().cloned())
+        .collect();
+        invoke_task(name, &args, &res.tasks, &mut ctx, &jobs);
+    }
+    exit(0);
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/* Emits the resolved dependency graph, prerequisites first, as Graphviz DOT or JSON.
+   Edges point from a task to each of its prerequisites. A line of 0 marks an
+   existing file that no task defines. */
+fn display_graph(tasks: &[Task], opts: &Options) -> ! {
+    if opts.display == Display::Dot {
+        println!("digraph rrake {{");
+        for task in tasks {
+            let shape = match task.get_kind() {
+                Kind::File => "note",
+                Kind::Directory => "folder",
+                _ => "box",
+            };
+            println!("    \"{}\" [shape={}, kind=\"{}\", desc=\"{}\", line={}];",
+            escape(task.get_name()), shape, task.get_kind().as_str(),
+            escape(task.get_desc()), task.get_line());
+        }
+        for task in tasks {
+            for depends in task.get_depends() {
+                println!("    \"{}\" -> \"{}\";", escape(task.get_name()), escape(depends));
+            }
+        }
+        println!("}}");
+        exit(0);
+    }
+    let nodes: Vec<String> = tasks.iter().map(|t| {
+        format!("    {{\"name\": \"{}\", \"kind\": \"{}\", \"desc\": \"{}\", \"line\": {}}}",
+        escape(t.get_name()), t.get_kind().as_str(), escape(t.get_desc()), t.get_line())
+    }).collect();
+    let mut edges: Vec<String> = Vec::new();
+    for task in tasks {
+        for depends in task.get_depends() {
+            edges.push(format!("    {{\"from\": \"{}\", \"to\": \"{}\"}}",
+            escape(task.get_name()), escape(depends)));
+        }
+    }
+    println!("{{\n  \"nodes\": [\n{}\n  ],\n  \"edges\": [\n{}\n  ]\n}}",
+    nodes.join(",\n"), edges.join(",\n"));
+    exit(0);
+}
+
+fn matching_tasks<'a>(program: &str, tasks: &'a [Task], opts: &Options) -> Vec<&'a Task> {
+    let p = match Regex::new(&opts.pattern) {
+        Ok(p) => p,
+        Err(_) => throw_bad_pattern_failure(program, &opts.pattern),
+    };
+    // Only --tasks and --describe leave out undescribed tasks.
+    let all = opts.show_all || opts.display == Display::Prereqs || opts.display == Display::Where;
+    let mut matching: Vec<&Task> = tasks.iter()
+    .filter(|t| (all || !t.get_desc().is_empty()) && p.is_match(t.get_name()))
+    .collect();
+    matching.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    matching
+}
+
+/* Lists tasks in Rake's format: aligned one-line comments for --tasks, full
+   indented descriptions for --describe, prerequisites for --prereqs and
+   definition sites for --where. */
+fn display_tasks(program: &str, tasks: &[Task], opts: &Options) -> ! {
+    let name = Path::new(program).file_name().map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_else(|| program.to_owned());
+    let matching = matching_tasks(program, tasks, opts);
+    if opts.display == Display::Prereqs {
+        for task in &matching {
+            println!("{} {}", name, task.get_name());
+            for depends in task.get_depends() {
+                println!("    {}", depends);
+            }
+        }
+        exit(0);
+    }
+    if opts.display == Display::Describe {
+        for task in &matching {
+            println!("{} {}", name, task.get_name_with_args());
+            for line in task.get_desc().lines() {
+                println!("    {}", line);
+            }
+            println!();
+        }
+        exit(0);
+    }
+    let width = matching.iter().map(|t| t.get_name().len()).max().unwrap_or(0);
+    if opts.display == Display::Where {
+        for task in &matching {
+            println!("{} {:width$}  {}:{}", name, task.get_name(),
+            task.get_file(), task.get_line(), width = width);
+        }
+        exit(0);
+    }
+    let width = matching.iter().map(|t| t.get_name_with_args().len()).max().unwrap_or(0);
+    for task in &matching {
+        if task.get_comment().is_empty() {
+            println!("{} {}", name, task.get_name_with_args());
+        } else {
+            println!("{} {:width$}  # {}", name, task.get_name_with_args(),
+            task.get_comment(), width = width);
         }
     }
     exit(0);
 }
 
-fn parse_tasks(program: &str, tasks: Vec<String>) -> Vec<String> {
-    let mut ptasks: Vec<String> = Vec::new();
+/* Task names with their arguments, as in `deploy[prod,eu]`. */
+fn parse_tasks(program: &str, tasks: Vec<String>) -> Vec<(String, Vec<String>)> {
+    let mut ptasks: Vec<(String, Vec<String>)> = Vec::new();
     for task in tasks {
         let p = Regex::new(&format!("{}|akefile.*", program)).unwrap();
-        if !p.is_match(&task) {
-            ptasks.push(task.to_owned());
+        if p.is_match(&task) {
+            continue;
+        }
+        match task.strip_suffix(']').and_then(|t| t.split_once('[')) {
+            Some((name, args)) if !args.trim().is_empty() => {
+                let args = args.split(',').map(|a| a.trim().to_owned()).collect();
+                ptasks.push((name.to_owned(), args));
+            },
+            Some((name, _)) => ptasks.push((name.to_owned(), Vec::new())),
+            None => ptasks.push((task, Vec::new())),
         }
     }
     ptasks
 }
 
-fn throw_no_task_failure(program: &str, task: &str) {
+fn throw_no_task_failure(program: &str, task: &str) -> ! {
     println!("{} aborted!", program);
     println!("Don't know how to build task '{}'\n", task);
-    exit(-1);   
+    exit(-1);
 }
 
-fn throw_not_found_failure(program: &str, rakefiles: &Vec<&str>) {
+fn throw_no_prereq_failure(program: &str, task: &str, parent: &str, line: usize) -> ! {
     println!("{} aborted!", program);
-    println!("No Rakefile found (looking for {})\n", rakefiles.join(", "));
+    println!("Don't know how to build task '{}' (prerequisite of '{}' at line {})\n",
+    task, parent, line);
     exit(-1);
 }
 
-fn throw_build_failiure(program: &str, task: &str, ec: i32, line: usize) {
+fn throw_cycle_failure(program: &str, cycle: &[&Task]) -> ! {
+    println!("{} aborted!", program);
+    let names: Vec<&str> = cycle.iter().map(|t| t.get_name()).collect();
+    println!("Circular dependency detected: {}", names.join(" => "));
+    for task in &cycle[..cycle.len() - 1] {
+        println!("    '{}' defined at line {}", task.get_name(), task.get_line());
+    }
+    println!();
+    exit(-1);
+}
+
+fn throw_not_found_failure(program: &str, rakefiles: &[&str], system: Option<&Path>) -> ! {
+    println!("{} aborted!", program);
+    println!("No Rakefile found (looking for {})", rakefiles.join(", "));
+    if let Some(system) = system {
+        println!("No system rakefiles found either (looking in {})", system.display());
+    }
+    println!();
+    exit(-1);
+}
+
+fn throw_directory_failure(program: &str, dir: &str) -> ! {
+    println!("{} aborted!", program);
+    println!("No such file or directory - {}", dir);
+    exit(-1);
+}
+
+fn throw_build_failiure(program: &str, task: &str, ec: i32, line: usize) -> ! {
     println!("{} aborted!", program);
     println!("Failed to build task '{}' at line {}", task, line);
     println!("Exited with code: {}", ec);
     exit(ec);
 }
 
-fn throw_bad_format_file(program: &str, file: &str) {
+fn throw_parse_failure(program: &str, rakefile: &str, e: &ParseError) -> ! {
+    println!("{} aborted!", program);
+    println!("{}:{}:{}: syntax error, {}", rakefile, e.span.line, e.span.col, e.message);
+    exit(-1);
+}
+
+/* An I/O error as Ruby reports it, e.g. "No such file or directory - in.c": */
+fn io_message(e: &io::Error, path: &str) -> String {
+    let reason = match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_owned(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_owned(),
+        _ => e.to_string(),
+    };
+    format!("{} - {}", reason, path)
+}
+
+fn throw_runtime_failure(program: &str, message: &str, line: usize) -> ! {
+    println!("{} aborted!", program);
+    println!("{} at line {}", message, line);
+    exit(-1);
+}
+
+fn throw_signal_failure(program: &str, task: &str, signal: i32, line: usize) -> ! {
+    println!("{} aborted!", program);
+    println!("Failed to build task '{}' at line {}", task, line);
+    println!("{}", describe_signal(signal));
+    exit(128 + signal);
+}
+
+fn throw_spawn_failure(program: &str, command: &str, e: &io::Error, line: usize) -> ! {
+    println!("{} aborted!", program);
+    println!("Could not run '{}' at line {}: {}", command, line, e);
+    exit(-1);
+}
+
+fn throw_bad_pattern_failure(program: &str, pattern: &str) -> ! {
+    println!("{} aborted!", program);
+    println!("Invalid task pattern '{}'", pattern);
+    exit(-1);
+}
+
+fn throw_bad_format_file(program: &str, file: &str) -> ! {
     println!("{} aborted!", program);
     println!("Provided file '{}' does not seem to be in Rakefile format.", file);
     exit(-1);
 }
 
+/* Where personal .rake files live: $RAKE_SYSTEM, else ~/.rake (%APPDATA%\\Rake on Windows). */
+fn system_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("RAKE_SYSTEM") {
+        return Some(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        env::var("APPDATA").ok().map(|d| Path::new(&d).join("Rake"))
+    } else {
+        env::var("HOME").ok().map(|d| Path::new(&d).join(".rake"))
+    }
+}
+
 fn display_version() {
     println!("rake in rust, version 0.1.0");
     exit(0);
 }
 
-fn display_usage(program: &str, code: i32) {
+fn display_usage(program: &str, code: i32) -> ! {
     println!("Rake in Rust.");
     println!("Ruby build tool implementation.");
     println!("Copyright 2017 Sam Saint-Pettersen.");
     println!("\nReleased under the MIT License.");
-    println!("\nUsage: {} [-f|--rakefile <rakefile>] [options] [task]", program);
+    println!("\nUsage: {} [-f|--rakefile <rakefile>] [options] [task...] [VAR=value...]", program);
     println!("\nOptions are:\n");
     println!("-q | --quiet: Do not print out to stdout other than sh stdout/stderr (Quiet mode).");
     println!("-e | --exits: Print exit codes for sh invokations.");
+    println!("-C | --directory <dir>: Change to dir before doing anything.");
+    println!("-N | --no-search: Do not search parent directories for the Rakefile.");
+    println!("-g | --system: Use the system-wide rakefiles in ~/.rake instead of the Rakefile.");
+    println!("-G | --no-system: Never use the system-wide rakefiles.");
+    println!("-R | --rakelibdir <dir>: Auto-import any .rake files in dir (default: rakelib).");
     println!("-i | --ignore-ec: Ignore bad exit codes and continue.");
     println!("-x | --ignore-ext: Ignore extension for Rakefile.");
     println!("-m | --ignore-format: Ignore format for Rakefile.");
+    println!("-T | --tasks [pattern]: Display the tasks (matching optional pattern) with descriptions, then exit.");
+    println!("-D | --describe [pattern]: Describe the tasks (matching optional pattern), then exit.");
+    println!("-P | --prereqs: Display the tasks and their prerequisites, then exit.");
+    println!("-W | --where [pattern]: Describe where the tasks (matching optional pattern) are defined, then exit.");
+    println!("--graph <dot|json>: Display the dependency graph of the task(s) as Graphviz DOT or JSON, then exit.");
+    println!("-A | --all: Show all tasks, even uncommented ones (in combination with -T or -D).");
+    println!("-n | --dry-run: Do a dry run without executing actions (implies --trace).");
+    println!("-t | --trace: Print out invocation and execution of each task.");
+    println!("-j | --jobs <n>: Run up to n independent tasks in parallel (default for multitask: cores + 4).");
     exit(code);
 }
 
@@ -558,53 +1697,155 @@ fn main() {
     let mut ignore = false;
     let mut ext = true;
     let mut format = true;
+    let mut jobs: usize = 0;
+    let mut display = Display::Run;
+    let mut pattern = String::new();
+    let mut show_all = false;
+    let mut dry_run = false;
+    let mut trace = false;
+    let mut rakelib = "rakelib".to_owned();
+    let mut search = true;
+    let mut system = false;
+    let mut no_system = false;
+    let mut directory = String::new();
+    let mut skip = false;
+    let assignment = Regex::new(r"^(\w+)=(.*)$").unwrap();
 
     if cli.get_num() > 1 {
         for (i, a) in cli.get_args().iter().enumerate() {
+            if skip {
+                skip = false;
+                continue;
+            }
             match a.trim() {
                 "-h" | "--help" => display_usage(&program, 0),
                 "-v" | "--version" => display_version(),
                 "-q" | "--quiet" => verbose = false,
                 "-e" | "--exits" => exit_codes = true,
-                "-f" | "--rakefile" | "--file" => srakefile = cli.next_argument(i),
+                "-f" | "--rakefile" | "--file" => {
+                    srakefile = cli.next_argument(i);
+                    skip = true;
+                },
+                "-T" | "--tasks" | "-D" | "--describe" | "-W" | "--where" => {
+                    display = match a.trim() {
+                        "-T" | "--tasks" => Display::Tasks,
+                        "-W" | "--where" => Display::Where,
+                        _ => Display::Describe,
+                    };
+                    let next = cli.next_argument(i);
+                    if !next.is_empty() && !next.starts_with('-') {
+                        pattern = next;
+                        skip = true;
+                    }
+                },
+                "-P" | "--prereqs" => display = Display::Prereqs,
+                "--graph" => {
+                    display = match cli.next_argument(i).as_str() {
+                        "dot" => Display::Dot,
+                        "json" => Display::Json,
+                        _ => display_usage(&program, -1),
+                    };
+                    skip = true;
+                },
+                "-A" | "--all" => show_all = true,
+                "-n" | "--dry-run" => {
+                    dry_run = true;
+                    trace = true;
+                },
+                "-t" | "--trace" => trace = true,
+                "-j" | "--jobs" => {
+                    jobs = match cli.next_argument(i).parse::<usize>() {
+                        Ok(jobs) if jobs > 0 => jobs,
+                        _ => display_usage(&program, -1),
+                    };
+                    skip = true;
+                },
+                "-N" | "--no-search" | "--nosearch" => search = false,
+                "-g" | "--system" => system = true,
+                "-G" | "--no-system" | "--nosystem" => no_system = true,
+                "-R" | "--rakelibdir" | "--rakelib" => {
+                    rakelib = cli.next_argument(i);
+                    skip = true;
+                },
+                "-C" | "--directory" => {
+                    directory = cli.next_argument(i);
+                    skip = true;
+                },
                 "-i" | "--ignore-ec" => ignore = true,
                 "-x" | "--ignore-ext" => ext = false,
                 "-m" | "--ignore-format" => format = false,
+                // Like Rake, NAME=value sets an environment variable for the run.
+                _ if assignment.is_match(a) => {
+                    let cap = assignment.captures(a).unwrap();
+                    env::set_var(&cap[1], &cap[2]);
+                },
                 _ => tasks.push(a.to_owned()),
             }
         }
     }
 
-    let opts = Options { 
-        verbose: verbose, 
-        exit_codes: exit_codes,
-        ignore: ignore,
+    let opts = Options {
+        verbose,
+        exit_codes,
+        ignore,
+        jobs,
+        display,
+        pattern,
+        show_all,
+        dry_run,
+        trace,
+        rakelib,
     };
 
     let mut tasks = parse_tasks(&program, tasks);
-    if tasks.len() == 0 {
-        tasks.push("default".to_owned());
+    if tasks.is_empty() {
+        tasks.push(("default".to_owned(), Vec::new()));
     }
 
+    if !directory.is_empty() && env::set_current_dir(&directory).is_err() {
+        throw_directory_failure(&program, &directory);
+    }
     let mut erakefile = String::new();
     if !srakefile.is_empty() {
         erakefile = srakefile.clone();
     }
-    if erakefile.is_empty() {
+    let original = env::current_dir().unwrap();
+    while erakefile.is_empty() {
         for rakefile in &rakefiles {
             if Path::new(&rakefile).exists() {
-                erakefile = format!("{}", rakefile);
+                erakefile = rakefile.to_string();
                 break; // Execute first found Rakefile variation.
             }
         }
+        if !erakefile.is_empty() || !search {
+            break;
+        }
+        // Like Rake, look in each parent directory in turn and run from there.
+        match env::current_dir().unwrap().parent() {
+            Some(parent) => env::set_current_dir(parent).unwrap(),
+            None => break,
+        }
+    }
+    // Like Rake, the system rakefiles stand in for a missing Rakefile, or replace it with -g.
+    let found = Path::new(&erakefile).exists();
+    let system_dir = if no_system { None } else { system_dir() };
+    if let Some(ref dir) = system_dir {
+        if (system || !found) && dir.is_dir() {
+            env::set_current_dir(&original).unwrap();
+            invoke_rakefile(&program, "", &dir.display().to_string(), &tasks, &opts);
+        }
+    }
+    let cwd = env::current_dir().unwrap();
+    if found && cwd != original && verbose {
+        eprintln!("(in {})", cwd.display());
     }
-    if Path::new(&erakefile).exists() {
+    if found {
         if (ext && !validate_extension(&erakefile))
-        || (format && !validate_rakefile(&erakefile)) {
+        || (format && !validate_rakefile(&program, &erakefile)) {
             throw_bad_format_file(&program, &erakefile);
         }
-        invoke_rakefile(&program, &erakefile, &tasks, &opts);
+        invoke_rakefile(&program, &erakefile, &opts.rakelib, &tasks, &opts);
     } else {
-        throw_not_found_failure(&program, &rakefiles);
+        throw_not_found_failure(&program, &rakefiles, system_dir.as_deref());
     }
 }
@@ -0,0 +1,32 @@
+use ast::Node;
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    name: String,
+    params: Vec<(String, Option<Node>)>,
+    body: Vec<Node>,
+}
+
+impl Method {
+    pub fn new(name: &str, params: Vec<(String, Option<Node>)>, body: Vec<Node>) -> Method {
+        Method {
+            name: name.to_owned(),
+            params,
+            body,
+        }
+    }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_params(&self) -> &[(String, Option<Node>)] {
+        &self.params
+    }
+    pub fn get_body(&self) -> &[Node] {
+        &self.body
+    }
+
+    /* The number of parameters without a default value: */
+    pub fn get_required(&self) -> usize {
+        self.params.iter().filter(|p| p.1.is_none()).count()
+    }
+}
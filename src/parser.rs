@@ -0,0 +1,717 @@
+use std::mem;
+
+use ast::{Block, Node, NodeKind, ParseError, Segment, Span, StrPart};
+use lexer::{self, Tok, Token};
+
+/* Binding power of the binary operators, loosest first, as in Ruby: */
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "==" | "!=" | "=~" | "!~" => Some(3),
+        "<" | "<=" | ">" | ">=" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        "**" => Some(7),
+        _ => None,
+    }
+}
+
+fn describe(tok: &Tok) -> String {
+    match *tok {
+        Tok::Ident(ref s) | Tok::Const(ref s) => format!("'{}'", s),
+        Tok::Label(ref s) => format!("'{}:'", s),
+        Tok::Sym(ref s) => format!("':{}'", s),
+        Tok::Str(_) => "string literal".to_owned(),
+        Tok::Regex(_) => "regexp literal".to_owned(),
+        Tok::Words(_) => "word list".to_owned(),
+        Tok::Int(n) => format!("'{}'", n),
+        Tok::Float(f) => format!("'{}'", f),
+        Tok::Op(ref op) => format!("'{}'", op),
+        Tok::Newline => "end of line".to_owned(),
+        Tok::Eof => "end of file".to_owned(),
+        ref t => format!("'{}'", match *t {
+            Tok::Do => "do",
+            Tok::End => "end",
+            Tok::True => "true",
+            Tok::False => "false",
+            Tok::Nil => "nil",
+            Tok::If => "if",
+            Tok::Unless => "unless",
+            Tok::While => "while",
+            Tok::Until => "until",
+            Tok::Break => "break",
+            Tok::Def => "def",
+            Tok::Return => "return",
+            Tok::Then => "then",
+            Tok::Else => "else",
+            Tok::Elsif => "elsif",
+            Tok::LParen => "(",
+            Tok::RParen => ")",
+            Tok::LBracket => "[",
+            Tok::RBracket => "]",
+            Tok::LBrace => "{",
+            Tok::RBrace => "}",
+            Tok::Comma => ",",
+            Tok::Dot => ".",
+            Tok::ColonColon => "::",
+            Tok::Arrow => "=>",
+            Tok::Assign => "=",
+            Tok::Pipe => "|",
+            _ => ";",
+        }),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Local variables assigned so far, which Ruby never reads as command calls.
+    locals: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn peek_at(&self, offset: usize) -> &Tok {
+        let i = self.pos + offset;
+        if i < self.tokens.len() {
+            &self.tokens[i].tok
+        } else {
+            &Tok::Eof
+        }
+    }
+
+    fn span(&self) -> Span {
+        self.tokens[self.pos].span
+    }
+
+    fn spaced(&self) -> bool {
+        self.tokens[self.pos].spaced
+    }
+
+    fn advance(&mut self) -> Tok {
+        let tok = self.tokens[self.pos].tok.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn accept(&mut self, tok: &Tok) -> bool {
+        if self.peek() == tok {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &Tok, what: &str) -> Result<(), ParseError> {
+        if self.accept(tok) {
+            Ok(())
+        } else {
+            Err(self.unexpected(what))
+        }
+    }
+
+    fn unexpected(&self, what: &str) -> ParseError {
+        ParseError::new(&format!("expected {}, found {}",
+        what, describe(self.peek())), self.span())
+    }
+
+    fn skip_newlines(&mut self) {
+        while *self.peek() == Tok::Newline || *self.peek() == Tok::Semi {
+            self.advance();
+        }
+    }
+
+    fn at_terminator(&self) -> bool {
+        matches!(*self.peek(), Tok::End | Tok::Else | Tok::Elsif | Tok::RBrace | Tok::Eof)
+    }
+
+    fn parse_stmts(&mut self) -> Result<Vec<Node>, ParseError> {
+        let mut stmts: Vec<Node> = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_terminator() {
+                break;
+            }
+            stmts.push(self.parse_stmt()?);
+            match *self.peek() {
+                Tok::Newline | Tok::Semi => {},
+                _ if self.at_terminator() => {},
+                _ => return Err(self.unexpected("end of statement")),
+            }
+        }
+        Ok(stmts)
+    }
+
+    /* A statement, with any trailing `if cond` or `unless cond` modifiers. */
+    fn parse_stmt(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_expr(false)?;
+        loop {
+            let span = self.span();
+            let negate = match *self.peek() {
+                Tok::If => false,
+                Tok::Unless => true,
+                _ => break,
+            };
+            self.advance();
+            let mut cond = self.parse_expr(false)?;
+            if negate {
+                cond = Node::new(NodeKind::Not(Box::new(cond)), span);
+            }
+            node = Node::new(NodeKind::If(Box::new(cond), vec![node], Vec::new()), span);
+        }
+        Ok(node)
+    }
+
+    /* `no_do` stops `do` being taken as a block, so that it binds to an outer command call. */
+    fn parse_expr(&mut self, no_do: bool) -> Result<Node, ParseError> {
+        let span = self.span();
+        let assignable = matches!((self.peek(), self.peek_at(1)),
+        (&Tok::Ident(_), &Tok::Assign) | (&Tok::Const(_), &Tok::Assign));
+        if assignable {
+            let target = match self.advance() {
+                Tok::Ident(name) => {
+                    self.locals.push(name.clone());
+                    Node::new(NodeKind::Ident(name), span)
+                },
+                Tok::Const(name) => Node::new(NodeKind::Const(name), span),
+                _ => unreachable!(),
+            };
+            self.advance();
+            self.skip_newlines();
+            let value = self.parse_expr(no_do)?;
+            return Ok(Node::new(NodeKind::Assign(Box::new(target), Box::new(value)), span));
+        }
+        // `x += 1` is short for `x = x + 1`.
+        let compound = match (self.peek(), self.peek_at(1)) {
+            (Tok::Ident(name), Tok::Op(op))
+            if ["+=", "-=", "*=", "/=", "**="].contains(&op.as_str()) => {
+                Some((name.clone(), op[..op.len() - 1].to_owned()))
+            },
+            _ => None,
+        };
+        if let Some((name, op)) = compound {
+            self.locals.push(name.clone());
+            self.advance();
+            self.advance();
+            self.skip_newlines();
+            let value = self.parse_expr(no_do)?;
+            let target = Node::new(NodeKind::Ident(name), span);
+            let sum = Node::new(NodeKind::Binary(op, Box::new(target.clone()), Box::new(value)), span);
+            return Ok(Node::new(NodeKind::Assign(Box::new(target), Box::new(sum)), span));
+        }
+        self.parse_binary(0, no_do)
+    }
+
+    /* Precedence climbing over the operators that bind at least as tightly as `min`. */
+    fn parse_binary(&mut self, min: u8, no_do: bool) -> Result<Node, ParseError> {
+        let mut node = self.parse_unary(no_do)?;
+        while let Tok::Op(ref op) = *self.peek() {
+            let (op, prec) = match precedence(op) {
+                Some(prec) if prec >= min => (op.clone(), prec),
+                _ => break,
+            };
+            let span = self.span();
+            self.advance();
+            self.skip_newlines();
+            // Only ** groups from the right.
+            let next = if op == "**" { prec } else { prec + 1 };
+            let right = Box::new(self.parse_binary(next, no_do)?);
+            let left = Box::new(node);
+            let kind = match op.as_str() {
+                "&&" => NodeKind::And(left, right),
+                "||" => NodeKind::Or(left, right),
+                _ => NodeKind::Binary(op, left, right),
+            };
+            node = Node::new(kind, span);
+        }
+        Ok(node)
+    }
+
+    fn parse_unary(&mut self, no_do: bool) -> Result<Node, ParseError> {
+        if *self.peek() == Tok::Op("!".to_owned()) {
+            let span = self.span();
+            self.advance();
+            let node = self.parse_unary(no_do)?;
+            return Ok(Node::new(NodeKind::Not(Box::new(node)), span));
+        }
+        if *self.peek() == Tok::Op("-".to_owned()) {
+            let span = self.span();
+            self.advance();
+            let node = self.parse_unary(no_do)?;
+            let kind = match node.kind {
+                NodeKind::Int(n) => NodeKind::Int(-n),
+                NodeKind::Float(f) => NodeKind::Float(-f),
+                _ => NodeKind::Neg(Box::new(node)),
+            };
+            return Ok(Node::new(kind, span));
+        }
+        self.parse_postfix(no_do)
+    }
+
+    fn parse_postfix(&mut self, no_do: bool) -> Result<Node, ParseError> {
+        let mut node = self.parse_primary(no_do)?;
+        loop {
+            match *self.peek() {
+                Tok::Dot | Tok::ColonColon => {
+                    self.advance();
+                    self.skip_newlines();
+                    let span = self.span();
+                    let name = match self.advance() {
+                        Tok::Ident(name) | Tok::Const(name) => name,
+                        _ => return Err(ParseError::new("expected method name", span)),
+                    };
+                    node = self.parse_call(Some(node), name, span, no_do)?;
+                },
+                Tok::LBracket if !self.spaced() => {
+                    let span = self.span();
+                    self.advance();
+                    let args = self.parse_list(&Tok::RBracket, "']'")?;
+                    node = Node::new(NodeKind::Index(Box::new(node), args), span);
+                },
+                _ => break,
+            }
+        }
+        Ok(node)
+    }
+
+    /* Whether the current token can begin an argument of a parenthesis-less call. */
+    fn starts_command_arg(&self) -> bool {
+        if !self.spaced() {
+            return false;
+        }
+        match *self.peek() {
+            Tok::Ident(_) | Tok::Const(_) | Tok::Label(_) | Tok::Sym(_) | Tok::Str(_)
+            | Tok::Int(_) | Tok::Float(_) | Tok::Regex(_) | Tok::LBracket | Tok::LParen
+            | Tok::True | Tok::False | Tok::Nil | Tok::Words(_) => true,
+            Tok::Op(ref op) => op == "!" || ((op == "-" || op == "*" || op == "&")
+                && !self.tokens[self.pos + 1].spaced),
+            _ => false,
+        }
+    }
+
+    fn parse_call(&mut self, recv: Option<Node>, name: String,
+    span: Span, no_do: bool) -> Result<Node, ParseError> {
+        let mut args: Vec<Node> = Vec::new();
+        let mut called = recv.is_some();
+        if *self.peek() == Tok::LParen && !self.spaced() {
+            self.advance();
+            args = self.parse_args(&Tok::RParen, "')'")?;
+            called = true;
+        } else if recv.is_none() && self.locals.contains(&name) {
+            // `x -1` subtracts when x is a local variable.
+            return Ok(Node::new(NodeKind::Ident(name), span));
+        } else if self.starts_command_arg() {
+            args = self.parse_command_args()?;
+            called = true;
+        }
+        let block = self.parse_block(no_do)?;
+        if !called && block.is_none() {
+            return Ok(Node::new(NodeKind::Ident(name), span));
+        }
+        let recv = recv.map(Box::new);
+        Ok(Node::new(NodeKind::Call { recv, name, args, block }, span))
+    }
+
+    fn parse_block(&mut self, no_do: bool) -> Result<Option<Block>, ParseError> {
+        let close = match *self.peek() {
+            Tok::Do if !no_do => Tok::End,
+            Tok::LBrace => Tok::RBrace,
+            _ => return Ok(None),
+        };
+        self.advance();
+        self.skip_newlines();
+        let mut params: Vec<String> = Vec::new();
+        if self.accept(&Tok::Pipe) {
+            loop {
+                let span = self.span();
+                match self.advance() {
+                    Tok::Ident(name) => params.push(name),
+                    Tok::Pipe => break,
+                    _ => return Err(ParseError::new("expected block parameter", span)),
+                }
+                if !self.accept(&Tok::Comma) {
+                    self.expect(&Tok::Pipe, "'|'")?;
+                    break;
+                }
+            }
+        }
+        let outer = self.locals.len();
+        self.locals.extend(params.iter().cloned());
+        let body = self.parse_stmts()?;
+        self.locals.truncate(outer);
+        self.expect(&close, if close == Tok::End { "'end'" } else { "'}'" })?;
+        Ok(Some(Block { params, body }))
+    }
+
+    /* Arguments up to a closing token; a trailing `key => value` run becomes a hash. */
+    fn parse_args(&mut self, close: &Tok, what: &str) -> Result<Vec<Node>, ParseError> {
+        let mut args: Vec<Node> = Vec::new();
+        let mut pairs: Vec<(Node, Node)> = Vec::new();
+        let mut hspan = self.span();
+        self.skip_newlines();
+        while self.peek() != close {
+            if pairs.is_empty() {
+                hspan = self.span();
+            }
+            self.parse_arg(&mut args, &mut pairs, false)?;
+            self.skip_newlines();
+            if !self.accept(&Tok::Comma) {
+                break;
+            }
+            self.skip_newlines();
+        }
+        self.expect(close, what)?;
+        if !pairs.is_empty() {
+            args.push(Node::new(NodeKind::Hash(pairs), hspan));
+        }
+        Ok(args)
+    }
+
+    fn parse_command_args(&mut self) -> Result<Vec<Node>, ParseError> {
+        let mut args: Vec<Node> = Vec::new();
+        let mut pairs: Vec<(Node, Node)> = Vec::new();
+        let hspan = self.span();
+        loop {
+            self.parse_arg(&mut args, &mut pairs, true)?;
+            if !self.accept(&Tok::Comma) {
+                break;
+            }
+            self.skip_newlines();
+        }
+        if !pairs.is_empty() {
+            args.push(Node::new(NodeKind::Hash(pairs), hspan));
+        }
+        Ok(args)
+    }
+
+    fn parse_arg(&mut self, args: &mut Vec<Node>, pairs: &mut Vec<(Node, Node)>,
+    no_do: bool) -> Result<(), ParseError> {
+        let span = self.span();
+        if let Tok::Label(name) = self.peek().clone() {
+            self.advance();
+            self.skip_newlines();
+            let value = self.parse_expr(no_do)?;
+            pairs.push((Node::new(NodeKind::Sym(name), span), value));
+            return Ok(());
+        }
+        let arg = self.parse_expr(no_do)?;
+        if self.accept(&Tok::Arrow) {
+            self.skip_newlines();
+            let value = self.parse_expr(no_do)?;
+            pairs.push((arg, value));
+        } else {
+            args.push(arg);
+        }
+        Ok(())
+    }
+
+    fn parse_list(&mut self, close: &Tok, what: &str) -> Result<Vec<Node>, ParseError> {
+        let mut items: Vec<Node> = Vec::new();
+        self.skip_newlines();
+        while self.peek() != close {
+            items.push(self.parse_expr(false)?);
+            self.skip_newlines();
+            if !self.accept(&Tok::Comma) {
+                break;
+            }
+            self.skip_newlines();
+        }
+        self.expect(close, what)?;
+        Ok(items)
+    }
+
+    fn parse_hash(&mut self, span: Span) -> Result<Node, ParseError> {
+        let mut pairs: Vec<(Node, Node)> = Vec::new();
+        self.skip_newlines();
+        while *self.peek() != Tok::RBrace {
+            let mut args: Vec<Node> = Vec::new();
+            self.parse_arg(&mut args, &mut pairs, false)?;
+            if !args.is_empty() {
+                return Err(self.unexpected("'=>'"));
+            }
+            self.skip_newlines();
+            if !self.accept(&Tok::Comma) {
+                break;
+            }
+            self.skip_newlines();
+        }
+        self.expect(&Tok::RBrace, "'}'")?;
+        Ok(Node::new(NodeKind::Hash(pairs), span))
+    }
+
+    fn parse_if(&mut self, span: Span) -> Result<Node, ParseError> {
+        let cond = self.parse_expr(true)?;
+        self.accept(&Tok::Then);
+        let body = self.parse_stmts()?;
+        let otherwise = match *self.peek() {
+            Tok::Elsif => {
+                let espan = self.span();
+                self.advance();
+                return Ok(Node::new(NodeKind::If(Box::new(cond), body,
+                vec![self.parse_if(espan)?]), span));
+            },
+            Tok::Else => {
+                self.advance();
+                self.parse_stmts()?
+            },
+            _ => Vec::new(),
+        };
+        self.expect(&Tok::End, "'end'")?;
+        Ok(Node::new(NodeKind::If(Box::new(cond), body, otherwise), span))
+    }
+
+    fn parse_unless(&mut self, span: Span) -> Result<Node, ParseError> {
+        let cond = self.parse_expr(true)?;
+        let cond = Node::new(NodeKind::Not(Box::new(cond)), span);
+        self.accept(&Tok::Then);
+        let body = self.parse_stmts()?;
+        let otherwise = if self.accept(&Tok::Else) { self.parse_stmts()? } else { Vec::new() };
+        self.expect(&Tok::End, "'end'")?;
+        Ok(Node::new(NodeKind::If(Box::new(cond), body, otherwise), span))
+    }
+
+    /* `while cond [do] ... end`, or `until`, which loops while the condition is false. */
+    fn parse_while(&mut self, span: Span, until: bool) -> Result<Node, ParseError> {
+        let mut cond = self.parse_expr(true)?;
+        if until {
+            cond = Node::new(NodeKind::Not(Box::new(cond)), span);
+        }
+        self.accept(&Tok::Do);
+        let body = self.parse_stmts()?;
+        self.expect(&Tok::End, "'end'")?;
+        Ok(Node::new(NodeKind::While(Box::new(cond), body), span))
+    }
+
+    /* `def name(a, b = 1) ... end`; the parentheses are optional. */
+    fn parse_def(&mut self, span: Span) -> Result<Node, ParseError> {
+        let name = match self.advance() {
+            Tok::Ident(name) | Tok::Const(name) => name,
+            _ => return Err(ParseError::new("expected method name", span)),
+        };
+        let parens = self.accept(&Tok::LParen);
+        // A method sees only its own parameters and locals.
+        let outer = mem::take(&mut self.locals);
+        let mut params: Vec<(String, Option<Node>)> = Vec::new();
+        while !matches!(*self.peek(), Tok::RParen | Tok::Newline | Tok::Semi) {
+            let pspan = self.span();
+            let param = match self.advance() {
+                Tok::Ident(param) => param,
+                _ => return Err(ParseError::new("expected parameter name", pspan)),
+            };
+            let default = if self.accept(&Tok::Assign) { Some(self.parse_expr(false)?) } else { None };
+            self.locals.push(param.clone());
+            params.push((param, default));
+            if !self.accept(&Tok::Comma) {
+                break;
+            }
+            self.skip_newlines();
+        }
+        if parens {
+            self.expect(&Tok::RParen, "')'")?;
+        }
+        let body = self.parse_stmts()?;
+        self.locals = outer;
+        self.expect(&Tok::End, "'end'")?;
+        Ok(Node::new(NodeKind::Def(name, params, body), span))
+    }
+
+    fn parse_primary(&mut self, no_do: bool) -> Result<Node, ParseError> {
+        let span = self.span();
+        let kind = match self.advance() {
+            Tok::Str(parts) => NodeKind::Str(segments(parts, &self.locals)?),
+            Tok::Int(n) => NodeKind::Int(n),
+            Tok::Float(f) => NodeKind::Float(f),
+            Tok::True => NodeKind::Bool(true),
+            Tok::False => NodeKind::Bool(false),
+            Tok::Nil => NodeKind::Nil,
+            Tok::Sym(s) => NodeKind::Sym(s),
+            Tok::Regex(r) => NodeKind::Regex(r),
+            Tok::Words(words) => NodeKind::Array(words.into_iter().map(|w| {
+                Node::new(NodeKind::Str(vec![Segment::Lit(w)]), span)
+            }).collect()),
+            Tok::Break => NodeKind::Break,
+            Tok::Def => return self.parse_def(span),
+            Tok::Return => {
+                let value = if self.starts_command_arg() {
+                    Some(Box::new(self.parse_expr(no_do)?))
+                } else {
+                    None
+                };
+                NodeKind::Return(value)
+            },
+            Tok::LBracket => NodeKind::Array(self.parse_list(&Tok::RBracket, "']'")?),
+            Tok::LBrace => return self.parse_hash(span),
+            Tok::LParen => {
+                self.skip_newlines();
+                let node = self.parse_expr(false)?;
+                self.skip_newlines();
+                self.expect(&Tok::RParen, "')'")?;
+                return Ok(node);
+            },
+            Tok::If => return self.parse_if(span),
+            Tok::Unless => return self.parse_unless(span),
+            Tok::While => return self.parse_while(span, false),
+            Tok::Until => return self.parse_while(span, true),
+            Tok::Ident(name) => return self.parse_call(None, name, span, no_do),
+            Tok::Const(name) => {
+                if *self.peek() == Tok::LParen && !self.spaced() {
+                    return self.parse_call(None, name, span, no_do);
+                }
+                NodeKind::Const(name)
+            },
+            tok => return Err(ParseError::new(
+                &format!("expected expression, found {}", describe(&tok)), span)),
+        };
+        Ok(Node::new(kind, span))
+    }
+}
+
+/* Moves a span within interpolated code to where that code sits in the file. */
+fn shift(span: &mut Span, at: Span) {
+    if span.line == 1 {
+        span.col += at.col - 1;
+    }
+    span.line += at.line - 1;
+}
+
+/* Parses the code of each #{...} in a string. */
+fn segments(parts: Vec<StrPart>, locals: &[String]) -> Result<Vec<Segment>, ParseError> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for part in parts {
+        match part {
+            StrPart::Lit(lit) => segments.push(Segment::Lit(lit)),
+            StrPart::Code(code, at) => {
+                let mut tokens = lexer::tokenize(&code).map_err(|mut e| {
+                    shift(&mut e.span, at);
+                    e
+                })?;
+                for token in &mut tokens {
+                    shift(&mut token.span, at);
+                }
+                let mut parser = Parser { tokens, pos: 0, locals: locals.to_vec() };
+                let stmts = parser.parse_stmts()?;
+                if *parser.peek() != Tok::Eof {
+                    return Err(parser.unexpected("'}'"));
+                }
+                segments.push(Segment::Code(stmts));
+            },
+        }
+    }
+    Ok(segments)
+}
+
+pub fn parse(source: &str) -> Result<Vec<Node>, ParseError> {
+    let tokens = lexer::tokenize(source)?;
+    let mut parser = Parser { tokens, pos: 0, locals: Vec::new() };
+    let program = parser.parse_stmts()?;
+    if *parser.peek() != Tok::Eof {
+        return Err(parser.unexpected("end of file"));
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use ast::{Node, NodeKind, Segment, Span};
+
+    /* Nodes written out as s-expressions, to compare parses at a glance: */
+    fn sexp(node: &Node) -> String {
+        let list = |nodes: &[Node]| nodes.iter().map(sexp).collect::<Vec<String>>().join(" ");
+        match node.kind {
+            NodeKind::Str(ref segments) => {
+                let parts: Vec<String> = segments.iter().map(|s| match *s {
+                    Segment::Lit(ref l) => format!("{:?}", l),
+                    Segment::Code(ref stmts) => format!("#{{{}}}", list(stmts)),
+                }).collect();
+                format!("(str {})", parts.join(" "))
+            },
+            NodeKind::Int(n) => n.to_string(),
+            NodeKind::Sym(ref s) => format!(":{}", s),
+            NodeKind::Ident(ref s) | NodeKind::Const(ref s) => s.clone(),
+            NodeKind::Array(ref items) => format!("[{}]", list(items)),
+            NodeKind::Assign(ref target, ref value) => format!("(= {} {})", sexp(target), sexp(value)),
+            NodeKind::Call { ref recv, ref name, ref args, ref block } => {
+                let mut s = match *recv {
+                    Some(ref recv) => format!("({}.{}", sexp(recv), name),
+                    None => format!("({}", name),
+                };
+                for arg in args {
+                    s.push(' ');
+                    s.push_str(&sexp(arg));
+                }
+                if let Some(ref block) = *block {
+                    s.push_str(&format!(" {{|{}| {}}}", block.params.join(","), list(&block.body)));
+                }
+                s + ")"
+            },
+            NodeKind::If(ref cond, ref body, ref otherwise) => {
+                format!("(if {} [{}] [{}])", sexp(cond), list(body), list(otherwise))
+            },
+            NodeKind::Def(ref name, ref params, ref body) => {
+                let params: Vec<&str> = params.iter().map(|p| p.0.as_str()).collect();
+                format!("(def {}({}) {})", name, params.join(","), list(body))
+            },
+            NodeKind::Not(ref operand) => format!("(! {})", sexp(operand)),
+            NodeKind::Neg(ref operand) => format!("(-@ {})", sexp(operand)),
+            NodeKind::And(ref left, ref right) => format!("(&& {} {})", sexp(left), sexp(right)),
+            NodeKind::Or(ref left, ref right) => format!("(|| {} {})", sexp(left), sexp(right)),
+            NodeKind::Binary(ref op, ref left, ref right) => format!("({} {} {})", op, sexp(left), sexp(right)),
+            ref kind => format!("{:?}", kind),
+        }
+    }
+
+    fn parsed(source: &str) -> Vec<String> {
+        parse(source).unwrap().iter().map(sexp).collect()
+    }
+
+    #[test]
+    fn locals_are_operands_and_other_names_take_command_args() {
+        assert_eq!(parsed("x = 5\nputs x -1"), vec!["(= x 5)", "(puts (- x 1))"]);
+        assert_eq!(parsed("puts x -1"), vec!["(puts (x -1))"]);
+        assert_eq!(parsed("[1].each { |i| puts i -1 }\nputs i -1"),
+        vec!["([1].each {|i| (puts (- i 1))})", "(puts (i -1))"]);
+        // A method has its own locals, as in Ruby.
+        assert_eq!(parsed("y = 1\ndef f(n)\n  y -1\n  n -1\nend"),
+        vec!["(= y 1)", "(def f(n) (y -1) (- n 1))"]);
+    }
+
+    #[test]
+    fn modifier_if_and_unless() {
+        assert_eq!(parsed("puts 1 if a && !b\nputs 2 unless c"),
+        vec!["(if (&& a (! b)) [(puts 1)] [])", "(if (! c) [(puts 2)] [])"]);
+        assert_eq!(parsed("x = 1 if y"), vec!["(if y [(= x 1)] [])"]);
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(parsed("1 + 2 * 3 ** 2 ** 1 - -a"), vec!["(- (+ 1 (* 2 (** 3 (** 2 1)))) (-@ a))"]);
+        assert_eq!(parsed("a || b && c == 1 < 2"), vec!["(|| a (&& b (== c (< 1 2))))"]);
+        assert_eq!(parsed("n += 2 * 3"), vec!["(= n (+ n (* 2 3)))"]);
+    }
+
+    #[test]
+    fn interpolations_are_parsed_with_the_surrounding_locals() {
+        assert_eq!(parsed("dir = 1\n\"#{dir}/bin #{files.join(' ')} #{\"x#{dir -1}\"}\""), vec![
+            "(= dir 1)",
+            "(str #{dir} \"/bin \" #{(files.join (str \" \"))} \" \" #{(str \"x\" #{(- dir 1)})})",
+        ]);
+    }
+
+    #[test]
+    fn error_spans() {
+        let e = parse("task :x do\n  puts 1\n").unwrap_err();
+        assert_eq!((e.message.as_str(), e.span), ("expected 'end', found end of file", Span { line: 3, col: 1 }));
+        let e = parse("puts 1\nputs \"a #{1 +} b\"").unwrap_err();
+        assert_eq!(e.span, Span { line: 2, col: 14 });
+        let e = parse("s = <<~EOS\n  #{)}\nEOS\n").unwrap_err();
+        assert_eq!(e.span, Span { line: 2, col: 5 });
+    }
+}
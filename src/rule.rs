@@ -0,0 +1,40 @@
+use ast::Block;
+use regex::Regex;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pattern: Regex,
+    sources: Vec<String>,
+    actions: Vec<Block>,
+    line: usize,
+}
+
+impl Rule {
+    pub fn new(pattern: Regex, sources: Vec<String>,
+    actions: Vec<Block>, line: usize) -> Rule {
+        Rule {
+            pattern,
+            sources,
+            actions,
+            line,
+        }
+    }
+    pub fn matches(&self, name: &str) -> bool {
+        self.pattern.is_match(name)
+    }
+    pub fn get_actions(&self) -> &[Block] {
+        &self.actions
+    }
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    /* A source of ".c" swaps the extension of the task name, as in Rake: */
+    pub fn sources_for(&self, name: &str) -> Vec<String> {
+        self.sources.iter().map(|s| match s.strip_prefix('.') {
+            Some(ext) => Path::new(name).with_extension(ext).display().to_string(),
+            None => s.clone(),
+        }).collect()
+    }
+}
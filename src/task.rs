@@ -1,36 +1,119 @@
+use ast::Block;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Normal,
+    Multi,
+    File,
+    Directory,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Normal => "task",
+            Kind::Multi => "multitask",
+            Kind::File => "file",
+            Kind::Directory => "directory",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Task {
+    kind: Kind,
     name: String,
-    depends: String,
-    command: String,
-    params: String,
+    depends: Vec<String>,
+    actions: Vec<Block>,
+    arg_names: Vec<String>,
+    desc: String,
+    scope: Vec<String>,
+    file: String,
     line: usize,
 }
 
 impl Task {
-    pub fn new(name: &str, depends: &str, 
-    command: &str, params: &str, line: usize) -> Task {
+    pub fn new(kind: Kind, name: &str, depends: Vec<String>,
+    actions: Vec<Block>, line: usize) -> Task {
         Task {
+            kind,
             name: name.to_owned(),
-            depends: depends.to_owned(),
-            command: command.to_owned(),
-            params: params.to_owned(),
-            line: line,
+            depends,
+            actions,
+            arg_names: Vec::new(),
+            desc: String::new(),
+            scope: Vec::new(),
+            file: String::new(),
+            line,
         }
     }
+    pub fn get_kind(&self) -> Kind {
+        self.kind
+    }
     pub fn get_name(&self) -> &str {
         &self.name
     }
-    pub fn get_depends(&self) -> &str {
+    pub fn get_depends(&self) -> &[String] {
         &self.depends
     }
-    pub fn get_command(&self) -> &str {
-        &self.command
+    pub fn set_depends(&mut self, depends: Vec<String>) {
+        self.depends = depends;
+    }
+    pub fn get_actions(&self) -> &[Block] {
+        &self.actions
+    }
+    pub fn set_arg_names(&mut self, arg_names: Vec<String>) {
+        self.arg_names = arg_names;
     }
-    pub fn get_params(&self) -> &str {
-        &self.params
+    pub fn get_arg_names(&self) -> &[String] {
+        &self.arg_names
+    }
+
+    /* The name as listed by --tasks, e.g. "deploy[env,region]": */
+    pub fn get_name_with_args(&self) -> String {
+        if self.arg_names.is_empty() {
+            return self.name.clone();
+        }
+        format!("{}[{}]", self.name, self.arg_names.join(","))
+    }
+    pub fn set_desc(&mut self, desc: &str) {
+        self.desc = desc.to_owned();
+    }
+    pub fn get_desc(&self) -> &str {
+        &self.desc
+    }
+
+    /* The first sentence of the description, as listed by --tasks: */
+    pub fn get_comment(&self) -> &str {
+        let line = self.desc.lines().next().unwrap_or("");
+        match line.find(". ") {
+            Some(end) => &line[..end + 1],
+            None => line,
+        }
+    }
+    pub fn set_scope(&mut self, scope: &[String]) {
+        self.scope = scope.to_vec();
+    }
+    pub fn get_scope(&self) -> &[String] {
+        &self.scope
+    }
+    pub fn set_file(&mut self, file: &str) {
+        self.file = file.to_owned();
+    }
+    pub fn get_file(&self) -> &str {
+        &self.file
     }
     pub fn get_line(&self) -> usize {
         self.line
     }
+
+    /* Redefining a task adds to its prerequisites and actions, as in Rake: */
+    pub fn enhance(&mut self, depends: Vec<String>, actions: Vec<Block>) {
+        for d in depends {
+            if !self.depends.contains(&d) {
+                self.depends.push(d);
+            }
+        }
+        self.actions.extend(actions);
+    }
 }
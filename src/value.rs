@@ -0,0 +1,380 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::mem;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Sym(String),
+    Str(String),
+    Array(Vec<Value>),
+    Hash(Vec<(Value, Value)>),
+}
+
+/* Ruby prints floats in full, except for very large or small magnitudes: */
+fn float_to_s(f: f64) -> String {
+    if f.is_nan() {
+        return "NaN".to_owned();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "Infinity".to_owned() } else { "-Infinity".to_owned() };
+    }
+    if f == 0.0 || (f.abs() >= 1e-4 && f.abs() < 1e16) {
+        let s = format!("{}", f);
+        return if s.contains('.') { s } else { format!("{}.0", s) };
+    }
+    let s = format!("{:e}", f);
+    let (mantissa, exponent) = s.split_at(s.find('e').unwrap());
+    let mantissa = if mantissa.contains('.') { mantissa.to_owned() } else { format!("{}.0", mantissa) };
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+fn quote(s: &str, json: bool) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if (c as u32) < 0x20 && json => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            '\x1b' => quoted.push_str("\\e"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\x{:02X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/* Ruby rounds integer division and modulo towards negative infinity.
+   None where the result overflows, as for the smallest integer over -1. */
+fn floor_div(a: i64, b: i64) -> Option<i64> {
+    let q = a.checked_div(b)?;
+    if a % b != 0 && ((a < 0) != (b < 0)) { Some(q - 1) } else { Some(q) }
+}
+
+fn floor_mod(a: i64, b: i64) -> Option<i64> {
+    let r = a.checked_rem(b)?;
+    if r != 0 && ((r < 0) != (b < 0)) { Some(r + b) } else { Some(r) }
+}
+
+/* The length of `len` items repeated `n` times, or an error where they could
+   never fit in memory, as for `[1, 2] * 2**62`. */
+fn repeat_len(len: usize, size: usize, n: i64) -> Result<usize, String> {
+    let total = usize::try_from(n).ok().and_then(|n| len.checked_mul(n));
+    let bytes = total.and_then(|total| total.checked_mul(size.max(1)));
+    match (total, bytes) {
+        (Some(total), Some(bytes)) if bytes <= isize::MAX as usize => Ok(total),
+        _ => Err("argument too big".to_owned()),
+    }
+}
+
+/* Integer powers; a negative exponent gives a Float, as in Ruby. */
+fn int_pow(a: i64, b: i64) -> Option<Value> {
+    match (a, b) {
+        (0, _) if b > 0 => Some(Value::Int(0)),
+        (1, _) if b >= 0 => Some(Value::Int(1)),
+        (-1, _) if b >= 0 => Some(Value::Int(if b % 2 == 0 { 1 } else { -1 })),
+        _ if b < 0 => Some(Value::Float(match i32::try_from(b) {
+            Ok(b) => (a as f64).powi(b),
+            Err(_) => (a as f64).powf(b as f64),
+        })),
+        _ => a.checked_pow(u32::try_from(b).ok()?).map(Value::Int),
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Nil => "NilClass",
+            Value::Bool(true) => "TrueClass",
+            Value::Bool(false) => "FalseClass",
+            Value::Int(_) => "Integer",
+            Value::Float(_) => "Float",
+            Value::Sym(_) => "Symbol",
+            Value::Str(_) => "String",
+            Value::Array(_) => "Array",
+            Value::Hash(_) => "Hash",
+        }
+    }
+
+    /* Only nil and false are false, as in Ruby. */
+    pub fn truthy(&self) -> bool {
+        !matches!(*self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn to_s(&self) -> String {
+        match *self {
+            Value::Nil => String::new(),
+            Value::Bool(b) => b.to_string(),
+            Value::Int(n) => n.to_string(),
+            Value::Float(f) => float_to_s(f),
+            Value::Sym(ref s) | Value::Str(ref s) => s.clone(),
+            Value::Array(_) | Value::Hash(_) => self.inspect(),
+        }
+    }
+
+    pub fn inspect(&self) -> String {
+        match *self {
+            Value::Nil => "nil".to_owned(),
+            Value::Sym(ref s) => format!(":{}", s),
+            Value::Str(ref s) => quote(s, false),
+            Value::Array(ref items) => {
+                let items: Vec<String> = items.iter().map(|i| i.inspect()).collect();
+                format!("[{}]", items.join(", "))
+            },
+            Value::Hash(ref pairs) => {
+                let pairs: Vec<String> = pairs.iter()
+                .map(|(k, v)| format!("{}=>{}", k.inspect(), v.inspect()))
+                .collect();
+                format!("{{{}}}", pairs.join(", "))
+            },
+            _ => self.to_s(),
+        }
+    }
+
+    /* Corresponding to Ruby's JSON.generate: */
+    pub fn to_json(&self) -> String {
+        match *self {
+            Value::Nil => "null".to_owned(),
+            Value::Sym(ref s) | Value::Str(ref s) => quote(s, true),
+            Value::Array(ref items) => {
+                let items: Vec<String> = items.iter().map(|i| i.to_json()).collect();
+                format!("[{}]", items.join(","))
+            },
+            Value::Hash(ref pairs) => {
+                let pairs: Vec<String> = pairs.iter()
+                .map(|(k, v)| format!("{}:{}", quote(&k.to_s(), true), v.to_json()))
+                .collect();
+                format!("{{{}}}", pairs.join(","))
+            },
+            _ => self.to_s(),
+        }
+    }
+
+    /* Numbers compare across Integer and Float; otherwise only like with like. */
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (&Value::Int(a), &Value::Int(b)) => Some(a.cmp(&b)),
+            (&Value::Int(a), &Value::Float(b)) => (a as f64).partial_cmp(&b),
+            (&Value::Float(a), &Value::Int(b)) => a.partial_cmp(&(b as f64)),
+            (&Value::Float(a), &Value::Float(b)) => a.partial_cmp(&b),
+            (&Value::Str(ref a), &Value::Str(ref b)) | (&Value::Sym(ref a), &Value::Sym(ref b)) => {
+                Some(a.cmp(b))
+            },
+            (Value::Array(a), Value::Array(b)) => {
+                for (x, y) in a.iter().zip(b) {
+                    match x.compare(y) {
+                        Some(Ordering::Equal) => {},
+                        ordering => return ordering,
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.equals(y))
+            },
+            (Value::Hash(a), Value::Hash(b)) => {
+                a.len() == b.len() && a.iter().all(|(k, v)| {
+                    b.iter().any(|(k2, v2)| k.equals(k2) && v.equals(v2))
+                })
+            },
+            (&Value::Int(_), _) | (&Value::Float(_), _) => {
+                self.compare(other) == Some(Ordering::Equal)
+            },
+            _ => self == other,
+        }
+    }
+
+    /* The value for a key of a hash: */
+    pub fn get(&self, key: &Value) -> Value {
+        match *self {
+            Value::Hash(ref pairs) => pairs.iter().find(|(k, _)| k.equals(key))
+            .map(|(_, v)| v.clone()).unwrap_or(Value::Nil),
+            _ => Value::Nil,
+        }
+    }
+
+    /* Unary minus, as "-@", and the numeric methods abs and succ: */
+    pub fn unary(&self, op: &str) -> Result<Value, String> {
+        let overflow = || "integer overflow".to_owned();
+        match (op, self) {
+            ("-@", &Value::Int(n)) => n.checked_neg().map(Value::Int).ok_or_else(overflow),
+            ("-@", &Value::Float(f)) => Ok(Value::Float(-f)),
+            ("abs", &Value::Int(n)) => n.checked_abs().map(Value::Int).ok_or_else(overflow),
+            ("abs", &Value::Float(f)) => Ok(Value::Float(f.abs())),
+            ("succ", &Value::Int(n)) => n.checked_add(1).map(Value::Int).ok_or_else(overflow),
+            _ => Err(format!("undefined method '{}' for {}", op, self.type_name())),
+        }
+    }
+
+    /* Binary operators; the error is the message Ruby would raise. */
+    pub fn operate(&self, op: &str, other: &Value) -> Result<Value, String> {
+        match op {
+            "==" => return Ok(Value::Bool(self.equals(other))),
+            "!=" => return Ok(Value::Bool(!self.equals(other))),
+            "<" | "<=" | ">" | ">=" => {
+                let ordering = match self.compare(other) {
+                    Some(ordering) => ordering,
+                    None => return Err(format!("comparison of {} with {} failed",
+                    self.type_name(), other.inspect())),
+                };
+                return Ok(Value::Bool(match op {
+                    "<" => ordering == Ordering::Less,
+                    "<=" => ordering != Ordering::Greater,
+                    ">" => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }));
+            },
+            _ => {},
+        }
+        let overflow = || "integer overflow".to_owned();
+        match (self, other) {
+            (&Value::Int(a), &Value::Int(b)) => match op {
+                "+" => a.checked_add(b).map(Value::Int).ok_or_else(overflow),
+                "-" => a.checked_sub(b).map(Value::Int).ok_or_else(overflow),
+                "*" => a.checked_mul(b).map(Value::Int).ok_or_else(overflow),
+                "/" | "%" if b == 0 => Err("divided by 0".to_owned()),
+                "/" => floor_div(a, b).map(Value::Int).ok_or_else(overflow),
+                "%" => floor_mod(a, b).map(Value::Int).ok_or_else(overflow),
+                "**" => int_pow(a, b).ok_or_else(overflow),
+                _ => Err(format!("undefined method '{}' for Integer", op)),
+            },
+            (&Value::Int(_), &Value::Float(_)) | (&Value::Float(_), &Value::Int(_))
+            | (&Value::Float(_), &Value::Float(_)) => {
+                let (a, b) = match (self, other) {
+                    (&Value::Int(a), &Value::Float(b)) => (a as f64, b),
+                    (&Value::Float(a), &Value::Int(b)) => (a, b as f64),
+                    (&Value::Float(a), &Value::Float(b)) => (a, b),
+                    _ => unreachable!(),
+                };
+                match op {
+                    "+" => Ok(Value::Float(a + b)),
+                    "-" => Ok(Value::Float(a - b)),
+                    "*" => Ok(Value::Float(a * b)),
+                    "/" => Ok(Value::Float(a / b)),
+                    "%" => Ok(Value::Float(a - b * (a / b).floor())),
+                    "**" => Ok(Value::Float(a.powf(b))),
+                    _ => Err(format!("undefined method '{}' for {}", op, self.type_name())),
+                }
+            },
+            (Value::Str(a), Value::Str(b)) if op == "+" => Ok(Value::Str(format!("{}{}", a, b))),
+            (Value::Str(a), &Value::Int(n)) if op == "*" && n >= 0 => {
+                repeat_len(a.len(), 1, n)?;
+                Ok(Value::Str(a.repeat(n as usize)))
+            },
+            (Value::Array(a), Value::Array(b)) if op == "+" => {
+                Ok(Value::Array(a.iter().chain(b).cloned().collect()))
+            },
+            (Value::Array(a), Value::Array(b)) if op == "-" => {
+                Ok(Value::Array(a.iter().filter(|x| !b.iter().any(|y| x.equals(y))).cloned().collect()))
+            },
+            (Value::Array(a), &Value::Int(n)) if op == "*" && n >= 0 => {
+                let len = repeat_len(a.len(), mem::size_of::<Value>(), n)?;
+                Ok(Value::Array(a.iter().cloned().cycle().take(len).collect()))
+            },
+            (&Value::Str(_), _) | (&Value::Array(_), _) if op == "+" => {
+                Err(format!("no implicit conversion of {} into {}", other.type_name(), self.type_name()))
+            },
+            _ => Err(format!("undefined method '{}' for {}", op, self.type_name())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+
+    fn int(n: i64) -> Value {
+        Value::Int(n)
+    }
+
+    #[test]
+    fn integer_division_rounds_down() {
+        assert_eq!(int(7).operate("/", &int(2)), Ok(int(3)));
+        assert_eq!(int(-7).operate("/", &int(2)), Ok(int(-4)));
+        assert_eq!(int(-7).operate("%", &int(2)), Ok(int(1)));
+        assert_eq!(int(7).operate("%", &int(-2)), Ok(int(-1)));
+        assert_eq!(int(1).operate("/", &int(0)), Err("divided by 0".to_owned()));
+    }
+
+    #[test]
+    fn integer_overflow_is_an_error() {
+        let overflow = Err("integer overflow".to_owned());
+        assert_eq!(int(i64::MIN).operate("/", &int(-1)), overflow);
+        assert_eq!(int(i64::MIN).operate("%", &int(-1)), overflow);
+        assert_eq!(int(i64::MAX).operate("+", &int(1)), overflow);
+        assert_eq!(int(2).operate("**", &int(64)), overflow);
+        assert_eq!(int(2).operate("**", &int(4294967296)), overflow);
+    }
+
+    #[test]
+    fn unary_overflow_is_an_error() {
+        let overflow = Err("integer overflow".to_owned());
+        assert_eq!(int(5).unary("-@"), Ok(int(-5)));
+        assert_eq!(int(-5).unary("abs"), Ok(int(5)));
+        assert_eq!(int(5).unary("succ"), Ok(int(6)));
+        assert_eq!(Value::Float(-1.5).unary("abs"), Ok(Value::Float(1.5)));
+        assert_eq!(int(i64::MIN).unary("-@"), overflow);
+        assert_eq!(int(i64::MIN).unary("abs"), overflow);
+        assert_eq!(int(i64::MAX).unary("succ"), overflow);
+        assert_eq!(Value::Nil.unary("-@"), Err("undefined method '-@' for NilClass".to_owned()));
+    }
+
+    #[test]
+    fn powers() {
+        assert_eq!(int(2).operate("**", &int(10)), Ok(int(1024)));
+        assert_eq!(int(2).operate("**", &int(-1)), Ok(Value::Float(0.5)));
+        assert_eq!(int(2).operate("**", &int(-4294967295)), Ok(Value::Float(0.0)));
+        assert_eq!(int(1).operate("**", &int(4294967296)), Ok(int(1)));
+        assert_eq!(int(-1).operate("**", &int(4294967297)), Ok(int(-1)));
+    }
+
+    #[test]
+    fn mixed_and_string_operators() {
+        assert_eq!(int(7).operate("*", &Value::Float(1.5)), Ok(Value::Float(10.5)));
+        assert_eq!(int(1).operate("==", &Value::Float(1.0)), Ok(Value::Bool(true)));
+        assert_eq!(Value::Str("ab".to_owned()).operate("*", &int(2)), Ok(Value::Str("abab".to_owned())));
+        assert_eq!(Value::Str("5".to_owned()).operate("+", &int(5)),
+        Err("no implicit conversion of Integer into String".to_owned()));
+        assert_eq!(int(1).operate("<", &Value::Nil), Err("comparison of Integer with nil failed".to_owned()));
+    }
+
+    #[test]
+    fn array_repetition_is_bounded() {
+        let array = Value::Array(vec![int(1), int(2)]);
+        assert_eq!(array.operate("*", &int(2)), Ok(Value::Array(vec![int(1), int(2), int(1), int(2)])));
+        assert_eq!(array.operate("*", &int(4611686018427387904)), Err("argument too big".to_owned()));
+        assert!(Value::Str("ab".to_owned()).operate("*", &int(i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn to_s_and_inspect() {
+        assert_eq!(Value::Nil.to_s(), "");
+        assert_eq!(Value::Nil.inspect(), "nil");
+        assert_eq!(Value::Float(3.0).to_s(), "3.0");
+        assert_eq!(Value::Float(1e20).to_s(), "1.0e+20");
+        assert_eq!(Value::Float(1.5e-5).to_s(), "1.5e-05");
+        assert_eq!(Value::Sym("a".to_owned()).to_s(), "a");
+        assert_eq!(Value::Sym("a".to_owned()).inspect(), ":a");
+        assert_eq!(Value::Str("a\"b".to_owned()).inspect(), "\"a\\\"b\"");
+        let hash = Value::Hash(vec![
+            (Value::Sym("a".to_owned()), int(1)),
+            (Value::Str("b".to_owned()), Value::Array(vec![Value::Nil, Value::Bool(true)])),
+        ]);
+        assert_eq!(hash.to_s(), "{:a=>1, \"b\"=>[nil, true]}");
+        assert_eq!(hash.to_json(), "{\"a\":1,\"b\":[null,true]}");
+    }
+}
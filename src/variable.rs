@@ -1,23 +1,25 @@
+use value::Value;
+
 #[derive(Debug, Clone)]
 pub struct Variable {
     key: String,
-    value: String, 
+    value: Value,
 }
 
 impl Variable {
-    pub fn new(key: &str, value: &str) -> Variable {
+    pub fn new(key: &str, value: Value) -> Variable {
         Variable {
             key: key.to_owned(),
-            value: value.to_owned(),
+            value,
         }
     }
     pub fn get_key(&self) -> String {
-        format!("{}", &self.key)
+        self.key.clone()
     }
-    pub fn set_value(&mut self, value: &str) {
-        self.value = value.to_owned();
+    pub fn set_value(&mut self, value: Value) {
+        self.value = value;
     }
-    pub fn get_value(&self) -> String {
-        format!("{}", &self.value)
+    pub fn get_value(&self) -> Value {
+        self.value.clone()
     }
 }